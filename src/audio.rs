@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
+
+use crate::level::*;
+use crate::rules::*;
+use crate::lib::kmath::*;
+
+// sound effects off the back of rules events, and the music
+// the actual playing is behind Backend so theres a null one for tests and machines without sound
+// the rodio one is behind the sound feature, off by default so building and testing needs no system audio libs
+
+pub const assets_path: &str = "./assets";
+pub const music_track: &str = "Tune_4.ogg";
+
+const snow_footsteps: &[&str] = &["snow-footstep-01.wav", "snow-footstep-02.wav", "snow-footstep-03.wav", "snow-footstep-04.wav", "snow-footstep-05.wav"];
+const ice_footsteps: &[&str] = &["footstep05.ogg"];
+
+// 0 to 1, multiplied together: master * sfx or master * music
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Volume {
+    pub master: f32,
+    pub sfx: f32,
+    pub music: f32,
+}
+
+impl Default for Volume {
+    fn default() -> Volume {
+        Volume { master: 1.0, sfx: 0.8, music: 0.5 }
+    }
+}
+
+pub trait Backend {
+    // bytes is the whole file, wav or ogg, shared with the cache so playing doesnt copy it
+    fn play(&mut self, name: &str, bytes: Arc<[u8]>, volume: f32);
+    fn play_music(&mut self, name: &str, bytes: Arc<[u8]>, volume: f32); // loops
+    fn set_music_volume(&mut self, volume: f32);
+}
+
+// plays nothing, just remembers what it was asked to play
+#[derive(Default)]
+pub struct NullBackend {
+    pub played: Vec<(String, f32)>,
+    pub music: Option<(String, f32)>,
+}
+
+impl Backend for NullBackend {
+    fn play(&mut self, name: &str, _bytes: Arc<[u8]>, volume: f32) {
+        self.played.push((name.to_owned(), volume));
+    }
+    fn play_music(&mut self, name: &str, _bytes: Arc<[u8]>, volume: f32) {
+        self.music = Some((name.to_owned(), volume));
+    }
+    fn set_music_volume(&mut self, volume: f32) {
+        if let Some((_, v)) = &mut self.music {
+            *v = volume;
+        }
+    }
+}
+
+#[cfg(feature = "sound")]
+pub struct RodioBackend {
+    _stream: rodio::OutputStream, // has to stay alive for anything to play
+    handle: rodio::OutputStreamHandle,
+    music: Option<rodio::Sink>,
+}
+
+#[cfg(feature = "sound")]
+impl RodioBackend {
+    pub fn new() -> Option<RodioBackend> {
+        let (stream, handle) = rodio::OutputStream::try_default().ok()?;
+        Some(RodioBackend { _stream: stream, handle, music: None })
+    }
+
+    fn sink(&self, bytes: Arc<[u8]>, volume: f32, looping: bool) -> Option<rodio::Sink> {
+        use rodio::Source;
+        let source = rodio::Decoder::new(std::io::Cursor::new(bytes)).ok()?;
+        let sink = rodio::Sink::try_new(&self.handle).ok()?;
+        sink.set_volume(volume);
+        if looping {
+            sink.append(source.repeat_infinite());
+        } else {
+            sink.append(source);
+        }
+        Some(sink)
+    }
+}
+
+#[cfg(feature = "sound")]
+impl Backend for RodioBackend {
+    fn play(&mut self, _name: &str, bytes: Arc<[u8]>, volume: f32) {
+        // the output stream mixes, each effect gets its own sink and is left to finish
+        if let Some(sink) = self.sink(bytes, volume, false) {
+            sink.detach();
+        }
+    }
+    fn play_music(&mut self, _name: &str, bytes: Arc<[u8]>, volume: f32) {
+        self.music = self.sink(bytes, volume, true);
+    }
+    fn set_music_volume(&mut self, volume: f32) {
+        if let Some(sink) = &self.music {
+            sink.set_volume(volume);
+        }
+    }
+}
+
+pub struct Audio {
+    backend: Box<dyn Backend>,
+    pub volume: Volume,
+    sounds: HashMap<String, Arc<[u8]>>, // loaded on first play
+    seed: u32,
+}
+
+impl Audio {
+    // the real thing if theres a sound device, otherwise quiet
+    pub fn new(volume: Volume) -> Audio {
+        #[cfg(feature = "sound")]
+        {
+            match RodioBackend::new() {
+                Some(backend) => return Audio::with_backend(Box::new(backend), volume),
+                None => println!("no sound device, audio is off"),
+            }
+        }
+        Audio::with_backend(Box::new(NullBackend::default()), volume)
+    }
+
+    pub fn with_backend(backend: Box<dyn Backend>, volume: Volume) -> Audio {
+        Audio {
+            backend,
+            volume,
+            sounds: HashMap::new(),
+            seed: 0,
+        }
+    }
+
+    fn load(&mut self, name: &str) -> Arc<[u8]> {
+        self.sounds.entry(name.to_owned()).or_insert_with(|| {
+            let mut bytes = Vec::new();
+            let path = format!("{}/{}", assets_path, name);
+            // missing files still get passed along empty so the null backend sees them, real ones cant decode it and stay quiet
+            if File::open(&path).and_then(|mut f| f.read_to_end(&mut bytes)).is_err() {
+                println!("couldnt load {}", path);
+            }
+            bytes.into()
+        }).clone()
+    }
+
+    pub fn play(&mut self, name: &str) {
+        let volume = self.volume.master * self.volume.sfx;
+        if volume <= 0.0 {
+            return;
+        }
+        let bytes = self.load(name);
+        self.backend.play(name, bytes, volume);
+    }
+
+    pub fn play_music(&mut self) {
+        let volume = self.volume.master * self.volume.music;
+        let bytes = self.load(music_track);
+        self.backend.play_music(music_track, bytes, volume);
+    }
+
+    pub fn set_volume(&mut self, volume: Volume) {
+        self.volume = volume;
+        self.backend.set_music_volume(volume.master * volume.music);
+    }
+
+    fn pick(&mut self, options: &[&'static str]) -> &'static str {
+        self.seed += 1;
+        let idx = (krand(self.seed) * options.len() as f32) as usize;
+        options[idx.min(options.len() - 1)]
+    }
+
+    // one sound per kind of thing per move, a slide is one slide noise not one per cell
+    pub fn events(&mut self, events: &[Event], level: &Level) {
+        let mut sounds: Vec<&'static str> = Vec::new();
+        for ev in events {
+            let sound = match ev {
+                Event::Stepped { to, .. } => {
+                    match level.tiles[(to.1 * level.w + to.0) as usize] {
+                        Tile::Ice => self.pick(ice_footsteps),
+                        _ => self.pick(snow_footsteps),
+                    }
+                },
+                Event::Pushed { .. } => "move.ogg",
+                Event::StartedSliding { .. } => "slide.wav",
+                Event::Stopped { .. } => "movefinal.ogg",
+                Event::Delivered { .. } => "double-pling-select.wav",
+                Event::EnteredPortal { .. } => "menu-select.wav",
+                Event::Detonated { .. } | Event::Felled { .. } => "negative.wav",
+                Event::Undo | Event::Redo | Event::Restart => "undo.ogg",
+                Event::Slid { .. } => continue,
+            };
+            if !sounds.contains(&sound) {
+                sounds.push(sound);
+            }
+        }
+        // footsteps are all different files but only want one of them
+        let mut stepped = false;
+        for sound in sounds {
+            if snow_footsteps.contains(&sound) || ice_footsteps.contains(&sound) {
+                if stepped {
+                    continue;
+                }
+                stepped = true;
+            }
+            self.play(sound);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    // so the test can look at what got played after handing the backend over
+    struct Shared(Rc<RefCell<NullBackend>>);
+
+    impl Backend for Shared {
+        fn play(&mut self, name: &str, bytes: Arc<[u8]>, volume: f32) { self.0.borrow_mut().play(name, bytes, volume) }
+        fn play_music(&mut self, name: &str, bytes: Arc<[u8]>, volume: f32) { self.0.borrow_mut().play_music(name, bytes, volume) }
+        fn set_music_volume(&mut self, volume: f32) { self.0.borrow_mut().set_music_volume(volume) }
+    }
+
+    fn audio() -> (Audio, Rc<RefCell<NullBackend>>) {
+        let backend = Rc::new(RefCell::new(NullBackend::default()));
+        (Audio::with_backend(Box::new(Shared(backend.clone())), Volume::default()), backend)
+    }
+
+    #[test]
+    fn one_sound_per_kind_per_move() {
+        let (mut audio, backend) = audio();
+        let mut li = level("audio\n########\n#pB/// #\n########").instance();
+        li.try_move((1, 0));
+        audio.events(&li.take_events(), &li.l);
+        let played: Vec<String> = backend.borrow().played.iter().map(|(name, _)| name.clone()).collect();
+        // stepped onto ice, pushed, both started sliding, the player bumped into the present at the end
+        assert_eq!(played, vec!["footstep05.ogg", "slide.wav", "move.ogg", "movefinal.ogg"]);
+    }
+
+    #[test]
+    fn snow_footsteps_vary() {
+        let (mut audio, backend) = audio();
+        let level = level("walk\n#####\n#p  #\n#####");
+        for _ in 0..20 {
+            audio.events(&[Event::Stepped { from: (1, 1), to: (2, 1), tick: 0 }], &level);
+        }
+        let played = &backend.borrow().played;
+        assert!(played.iter().all(|(name, _)| snow_footsteps.contains(&name.as_str())));
+        assert!(played.iter().any(|(name, _)| *name != played[0].0));
+    }
+
+    #[test]
+    fn volume_applies() {
+        let (mut audio, backend) = audio();
+        audio.play_music();
+        audio.set_volume(Volume { master: 0.5, sfx: 0.5, music: 0.4 });
+        audio.play("win.ogg");
+        assert_eq!(backend.borrow().music, Some((music_track.to_owned(), 0.2)));
+        assert_eq!(backend.borrow().played, vec![("win.ogg".to_owned(), 0.25)]);
+        audio.set_volume(Volume { master: 0.0, sfx: 1.0, music: 1.0 });
+        audio.play("win.ogg");
+        assert_eq!(backend.borrow().played.len(), 1);
+    }
+}
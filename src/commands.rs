@@ -0,0 +1,712 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::level::*;
+use crate::instance::*;
+use crate::session::*;
+use crate::level_repository::LevelRepository;
+use crate::level_repository::list_backups;
+use crate::solver::*;
+use crate::xsb::*;
+use crate::replay::*;
+use crate::lib::kinput::*;
+
+// every terminal command in one table: name, arguments, help and what it does
+// parsing, usage errors and help all come off this, so a new command is just a new entry and a handler
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArgKind {
+    Word,
+    Level, // a level name, same as a word but completes from the repository
+    Number(u32, u32), // smallest and biggest allowed
+    Choice(&'static [&'static str]),
+}
+
+const no_limit: u32 = i32::MAX as u32; // numbers mostly end up as i32, dont let them wrap
+const any_number: ArgKind = ArgKind::Number(0, no_limit);
+pub const max_level_dim: u32 = 200;
+
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub optional: bool, // only at the end
+}
+
+const fn arg(name: &'static str, kind: ArgKind) -> ArgSpec {
+    ArgSpec { name, kind, optional: false }
+}
+
+const fn optional(name: &'static str, kind: ArgKind) -> ArgSpec {
+    ArgSpec { name, kind, optional: true }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Arg {
+    Word(String),
+    Number(u32),
+}
+
+impl Arg {
+    pub fn word(&self) -> String {
+        match self {
+            Arg::Word(w) => w.clone(),
+            Arg::Number(n) => n.to_string(),
+        }
+    }
+    pub fn number(&self) -> u32 {
+        match self {
+            Arg::Number(n) => *n,
+            Arg::Word(w) => unreachable!("{} isnt a number, parse only gives numbers for number args", w),
+        }
+    }
+}
+
+pub struct Command {
+    pub name: &'static str,
+    pub args: &'static [ArgSpec],
+    pub help: &'static str,
+    pub run: fn(&mut Session, &[Arg]) -> Result<(), String>, // errors get printed, or counted in a script
+}
+
+impl Command {
+    pub fn usage(&self) -> String {
+        let mut usage = self.name.to_owned();
+        for a in self.args {
+            let name = match a.kind {
+                ArgKind::Choice(options) => options.join("|"),
+                _ => a.name.to_owned(),
+            };
+            usage.push_str(&if a.optional { format!(" [{}]", name) } else { format!(" <{}>", name) });
+        }
+        usage
+    }
+
+    // "2 numbers", "a word and a number", for argument errors
+    fn expects(&self) -> String {
+        let describe = |kind: ArgKind| match kind {
+            ArgKind::Number(_, _) => "number",
+            ArgKind::Level => "level name",
+            ArgKind::Choice(_) => "choice",
+            ArgKind::Word => "word",
+        };
+        let required: Vec<&ArgSpec> = self.args.iter().filter(|a| !a.optional).collect();
+        let mut out = if required.is_empty() {
+            "nothing".to_owned()
+        } else if required.iter().all(|a| describe(a.kind) == describe(required[0].kind)) {
+            match required.len() {
+                1 => format!("a {}", describe(required[0].kind)),
+                n => format!("{} {}s", n, describe(required[0].kind)),
+            }
+        } else {
+            required.iter().map(|a| format!("a {}", describe(a.kind))).collect::<Vec<_>>().join(" and ")
+        };
+        if let Some(extra) = self.args.iter().find(|a| a.optional) {
+            out = if required.is_empty() {
+                format!("nothing or a {}", describe(extra.kind))
+            } else {
+                format!("{}, maybe then a {}", out, describe(extra.kind))
+            };
+        }
+        out
+    }
+}
+
+pub const commands: &[Command] = &[
+    Command { name: "help", args: &[optional("command", ArgKind::Word)], help: "list commands, or explain one", run: help },
+    Command { name: "clear", args: &[], help: "clear the terminal, history stays", run: clear },
+    Command { name: "run", args: &[arg("file", ArgKind::Word)], help: "run a file of commands, one per line", run },
+    Command { name: "new", args: &[arg("level", ArgKind::Level)], help: "start a new empty level", run: new },
+    Command { name: "open", args: &[arg("level", ArgKind::Level)], help: "open a level for editing", run: open },
+    Command { name: "save", args: &[], help: "save the current level", run: save },
+    Command { name: "saveas", args: &[arg("level", ArgKind::Level)], help: "save the current level under a new name", run: saveas },
+    Command { name: "rename", args: &[arg("old", ArgKind::Level), arg("new", ArgKind::Word)], help: "rename a level, portals follow it", run: rename },
+    Command { name: "delete", args: &[arg("level", ArgKind::Level)], help: "delete a level, asks twice", run: delete },
+    Command { name: "copy", args: &[arg("level", ArgKind::Level), arg("new", ArgKind::Word)], help: "copy a level under a new name", run: copy },
+    Command { name: "import", args: &[arg("file", ArgKind::Word)], help: "import levels from .lvl, .sok or .xsb", run: import },
+    Command { name: "export", args: &[arg("file", ArgKind::Word)], help: "export the current level, .lvl or xsb", run: export },
+    Command { name: "list", args: &[], help: "list saved levels", run: list },
+    Command { name: "play", args: &[], help: "save and play the current level", run: play },
+    Command { name: "replay", args: &[], help: "watch the best recorded solution", run: replay },
+    Command { name: "solve", args: &[], help: "look for a solution", run: solve_level },
+    Command { name: "par", args: &[optional("moves", any_number)], help: "set par, or work it out with the solver", run: par },
+    Command { name: "dims", args: &[arg("w", ArgKind::Number(1, max_level_dim)), arg("h", ArgKind::Number(1, max_level_dim))], help: "resize the current level", run: dims },
+    Command { name: "link", args: &[arg("level", ArgKind::Level)], help: "where placed portals go", run: link },
+    Command { name: "tokens", args: &[arg("tokens", any_number)], help: "tokens placed portals need", run: tokens },
+    Command { name: "profile", args: &[arg("name", ArgKind::Word)], help: "switch profile, making it if its new", run: profile },
+    Command { name: "reset", args: &[], help: "forget completed levels for this profile", run: reset },
+    Command { name: "backups", args: &[], help: "list backups of the levels file", run: backups },
+    Command { name: "restore", args: &[arg("backup", ArgKind::Word)], help: "restore a backup by number or name", run: restore },
+    Command { name: "volume", args: &[arg("channel", ArgKind::Choice(&["master", "sfx", "music"])), arg("percent", ArgKind::Number(0, 100))], help: "set volume, 0 to 100", run: volume },
+    Command { name: "repeat", args: &[arg("delay", any_number), arg("interval", ArgKind::Number(10, no_limit))], help: "key repeat timing in ms", run: repeat },
+];
+
+pub fn find_command(name: &str) -> Option<&'static Command> {
+    commands.iter().find(|c| c.name == name)
+}
+
+// split on spaces, except inside double quotes so "first level" is one argument
+// an unclosed quote runs to the end of the line
+pub fn tokenize(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    let mut started = false; // so "" is an argument
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                started = true;
+            },
+            ' ' if !quoted => {
+                if started {
+                    words.push(std::mem::take(&mut word));
+                    started = false;
+                }
+            },
+            c => {
+                word.push(c);
+                started = true;
+            },
+        }
+    }
+    if started {
+        words.push(word);
+    }
+    words
+}
+
+// the other way, for putting a name back into a command line
+pub fn quote(word: &str) -> String {
+    if word.is_empty() || word.contains(' ') {
+        format!("\"{}\"", word)
+    } else {
+        word.to_owned()
+    }
+}
+
+// what the word under the cursor could be: where it starts and the full words it could be, unquoted
+// line is everything up to the cursor
+pub fn complete(line: &str, level_names: &[String]) -> (usize, Vec<String>) {
+    let quotes = line.matches('"').count();
+    let start = if quotes % 2 == 1 {
+        line.rfind('"').unwrap()
+    } else {
+        line.rfind(' ').map(|i| i + 1).unwrap_or(0)
+    };
+    let partial = line[start..].trim_start_matches('"');
+    let before = tokenize(&line[..start]);
+    let options: Vec<String> = match before.first().and_then(|name| find_command(name)) {
+        None if before.is_empty() => commands.iter().map(|c| c.name.to_owned()).collect(),
+        None => Vec::new(),
+        Some(command) => match command.args.get(before.len() - 1).map(|a| a.kind) {
+            Some(ArgKind::Level) => level_names.to_vec(),
+            Some(ArgKind::Choice(options)) => options.iter().map(|o| (*o).to_owned()).collect(),
+            Some(ArgKind::Word) if command.name == "help" => commands.iter().map(|c| c.name.to_owned()).collect(),
+            _ => Vec::new(),
+        },
+    };
+    let mut matches: Vec<String> = options.into_iter().filter(|o| o.starts_with(partial)).collect();
+    matches.sort();
+    (start, matches)
+}
+
+pub fn parse(line: &str) -> Result<(&'static Command, Vec<Arg>), String> {
+    let words = tokenize(line);
+    let name = words.first().ok_or("".to_owned())?;
+    let command = find_command(name).ok_or(format!("bad command: {}, try help", name))?;
+    let given = &words[1..];
+    let required = command.args.iter().filter(|a| !a.optional).count();
+    if given.len() < required || given.len() > command.args.len() {
+        return Err(format!("{} expects {}: {}", command.name, command.expects(), command.usage()));
+    }
+    let mut args = Vec::new();
+    for (spec, word) in command.args.iter().zip(given.iter()) {
+        args.push(match spec.kind {
+            ArgKind::Number(min, max) => {
+                let n = word.parse::<u32>().map_err(|_| format!("{} should be a number, not {}: {}", spec.name, word, command.usage()))?;
+                if n < min || n > max {
+                    let range = if max != no_limit {
+                        format!("{} to {}", min, max)
+                    } else if min > 0 {
+                        format!("at least {}", min)
+                    } else {
+                        format!("at most {}", max)
+                    };
+                    return Err(format!("{} should be {}, not {}", spec.name, range, n));
+                }
+                Arg::Number(n)
+            },
+            ArgKind::Choice(options) if !options.contains(&word.as_str()) => return Err(format!("{} should be one of {}", spec.name, options.join(", "))),
+            _ => Arg::Word(word.clone()),
+        });
+    }
+    Ok((command, args))
+}
+
+// parse and run one line, same for typed and scripted
+pub fn execute(session: &mut Session, line: &str) -> Result<(), String> {
+    if line.trim().is_empty() {
+        return Ok(());
+    }
+    let (command, args) = parse(line)?;
+    // delete needs asking twice in a row
+    if command.name != "delete" {
+        session.pending_delete = None;
+    }
+    (command.run)(session, &args)
+}
+
+pub fn run_command(session: &mut Session, line: &str) {
+    if let Err(e) = execute(session, line) {
+        session.terminal.tprint(e);
+    }
+}
+
+// every line goes, a bad one doesnt stop the rest. returns how many failed
+// blank lines and lines starting with # are skipped
+pub fn run_script(session: &mut Session, path: &str) -> Result<usize, String> {
+    if session.scripts.iter().any(|p| p == path) {
+        return Err(format!("{} is already running", path));
+    }
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("couldnt read {}: {}", path, e))?;
+    session.scripts.push(path.to_owned());
+    let mut failed = 0;
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // echoed but not remembered, up arrow only brings back what was typed, which is the run line
+        session.terminal.tprint(format!(">{}", line));
+        if let Err(e) = execute(session, line) {
+            session.terminal.tprint(format!("{}:{}: {}", path, i + 1, e));
+            failed += 1;
+        }
+    }
+    session.scripts.pop();
+    Ok(failed)
+}
+
+// --run from the command line: no window, no sound, the terminal goes to stdout. false if anything failed
+pub fn batch(path: &str) -> bool {
+    let mut session = Session::new_quiet();
+    let result = run_script(&mut session, path);
+    for line in session.terminal.terminal_lines.iter() {
+        println!("{}", line);
+    }
+    match result {
+        Ok(0) => true,
+        Ok(failed) => {
+            println!("{} lines failed", failed);
+            false
+        },
+        Err(e) => {
+            println!("{}", e);
+            false
+        },
+    }
+}
+
+fn help(session: &mut Session, args: &[Arg]) -> Result<(), String> {
+    match args.first().map(|a| a.word()) {
+        Some(name) => {
+            let command = find_command(&name).ok_or(format!("no command {}", name))?;
+            session.terminal.tprint(command.usage());
+            session.terminal.tprint(format!("  {}", command.help));
+        },
+        None => {
+            for command in commands {
+                session.terminal.tprint(format!("{} - {}", command.usage(), command.help));
+            }
+            session.terminal.tprint("names with spaces go in quotes: open \"first level\"".to_owned());
+        },
+    }
+    Ok(())
+}
+
+fn clear(session: &mut Session, _args: &[Arg]) -> Result<(), String> {
+    session.terminal.clear();
+    Ok(())
+}
+
+fn run(session: &mut Session, args: &[Arg]) -> Result<(), String> {
+    let path = args[0].word();
+    match run_script(session, &path)? {
+        0 => Ok(()),
+        failed => Err(format!("{} lines of {} failed", failed, path)),
+    }
+}
+
+fn new(session: &mut Session, args: &[Arg]) -> Result<(), String> {
+    let name = args[0].word();
+    if session.level_repository.contains_level(&name) {
+        return Err(format!("level {} already exists", name));
+    }
+    session.current_level = Level::new_empty(name);
+    Ok(())
+}
+
+fn open(session: &mut Session, args: &[Arg]) -> Result<(), String> {
+    let name = args[0].word();
+    session.current_level = session.level_repository.get_level(&name).ok_or(format!("level {} not found", name))?;
+    Ok(())
+}
+
+fn save(session: &mut Session, _args: &[Arg]) -> Result<(), String> {
+    session.save_current_level()
+}
+
+fn saveas(session: &mut Session, args: &[Arg]) -> Result<(), String> {
+    let name = args[0].word();
+    if session.level_repository.contains_level(&name) {
+        return Err(format!("level {} already exists", name));
+    }
+    // only renamed once its saved, a failed save leaves you editing what you had
+    let old = std::mem::replace(&mut session.current_level.title, name);
+    let result = session.save_current_level();
+    if result.is_err() {
+        session.current_level.title = old;
+    }
+    result
+}
+
+fn rename(session: &mut Session, args: &[Arg]) -> Result<(), String> {
+    let (old, new) = (args[0].word(), args[1].word());
+    let result = session.level_repository.rename_level(&old, new.clone());
+    session.report(result)?;
+    if session.current_level.title == old {
+        session.current_level.title = new;
+    }
+    Ok(())
+}
+
+fn delete(session: &mut Session, args: &[Arg]) -> Result<(), String> {
+    let name = args[0].word();
+    let pending_delete = session.pending_delete.take();
+    if !session.level_repository.contains_level(&name) {
+        return Err(format!("level {} not found", name));
+    }
+    if pending_delete.as_ref() == Some(&name) {
+        let result = session.level_repository.delete_level(&name);
+        session.report(result)?;
+        session.terminal.tprint(format!("deleted {}", name));
+    } else {
+        session.terminal.tprint(format!("delete {} again to confirm", name));
+        session.pending_delete = Some(name);
+    }
+    Ok(())
+}
+
+fn copy(session: &mut Session, args: &[Arg]) -> Result<(), String> {
+    let result = session.level_repository.copy_level(&args[0].word(), args[1].word(), session.profiles.current.clone());
+    session.report(result)
+}
+
+fn import(session: &mut Session, args: &[Arg]) -> Result<(), String> {
+    let path = args[0].word();
+    // our text format, sok or xsb, extension optional
+    let (path, contents) = [path.clone(), format!("{}.lvl", path), format!("{}.sok", path), format!("{}.xsb", path)].iter()
+        .find_map(|p| std::fs::read_to_string(p).ok().map(|contents| (p.clone(), contents)))
+        .ok_or(format!("couldnt read {}", path))?;
+    let results = if Path::new(&path).extension().is_some_and(|ext| ext == "lvl") {
+        vec![Level::from_string(&contents).ok_or(format!("{} isnt a level", path))]
+    } else {
+        levels_from_sok(&contents)
+    };
+    let found = results.len();
+    let (levels, failed): (Vec<_>, Vec<_>) = results.into_iter().partition(|r| r.is_ok());
+    let levels = levels.into_iter().map(|r| r.unwrap()).collect();
+    let (imported, problems) = session.level_repository.import_levels(levels, session.profiles.current.clone())
+        .map_err(|e| format!("couldnt save: {}", e))?;
+    session.terminal.tprint(format!("imported {} of {} levels", imported.len(), found));
+    for name in imported {
+        session.terminal.tprint(format!("  {}", name));
+    }
+    for error in failed.into_iter().filter_map(|r| r.err()) {
+        session.terminal.tprint(format!("  couldnt read {}", error));
+    }
+    for problem in problems {
+        session.terminal.tprint(problem);
+    }
+    Ok(())
+}
+
+fn export(session: &mut Session, args: &[Arg]) -> Result<(), String> {
+    let path = args[0].word();
+    let path = if Path::new(&path).extension().is_some() { path } else { format!("{}.xsb", path) };
+    let contents = if Path::new(&path).extension().is_some_and(|ext| ext == "lvl") {
+        format!("{}\n", session.current_level)
+    } else {
+        let (sok, lossy) = levels_to_sok(std::slice::from_ref(&session.current_level));
+        for problem in lossy {
+            session.terminal.tprint(format!("lossy: {}", problem));
+        }
+        sok
+    };
+    std::fs::write(&path, contents).map_err(|e| format!("couldnt write {}: {}", path, e))?;
+    session.terminal.tprint(format!("exported to {}", path));
+    Ok(())
+}
+
+fn list(session: &mut Session, _args: &[Arg]) -> Result<(), String> {
+    let mut level_names: Vec<String> = session.level_repository.data.keys().cloned().collect();
+    level_names.sort();
+    for name in level_names {
+        session.terminal.tprint(format!("  {}", name));
+    }
+    Ok(())
+}
+
+fn play(session: &mut Session, _args: &[Arg]) -> Result<(), String> {
+    // a locked repository cant save but the level can still be played
+    if let Err(e) = session.save_current_level() {
+        session.terminal.tprint(e);
+    }
+    session.current_instance = Some(Instance::new(session.current_level.instance(), session.level_repository.par_of(&session.current_level.title)));
+    session.return_stack.clear();
+    session.profiles.profile_mut().last_level = Some(session.level_repository.progress_key(&session.current_level.title));
+    session.profiles.save();
+    // maybe check theres a player, an objective, etc
+    Ok(())
+}
+
+fn replay(session: &mut Session, _args: &[Arg]) -> Result<(), String> {
+    let moves = session.level_repository.solution_of(&session.current_level.title).and_then(|s| decode_moves(&s))
+        .ok_or(format!("no solution recorded for {}", session.current_level.title))?;
+    session.terminal.tprint(format!("replaying {} moves", moves.len()));
+    session.current_instance = Some(Instance::new_replay(session.current_level.instance(), session.level_repository.par_of(&session.current_level.title), moves));
+    session.return_stack.clear();
+    Ok(())
+}
+
+fn solve_level(session: &mut Session, _args: &[Arg]) -> Result<(), String> {
+    match solve(&session.current_level, solver_max_states) {
+        SolveResult::Solved { moves, nodes } => {
+            session.terminal.tprint(format!("solved in {} moves ({} states)", moves.len(), nodes));
+            session.terminal.tprint(format!("  {}", encode_moves(&moves)));
+        },
+        SolveResult::Unsolvable { nodes } => session.terminal.tprint(format!("unsolvable ({} states)", nodes)),
+        SolveResult::Exhausted { nodes } => session.terminal.tprint(format!("no solution within {} states", nodes)),
+    }
+    Ok(())
+}
+
+fn par(session: &mut Session, args: &[Arg]) -> Result<(), String> {
+    let par = match args.first() {
+        Some(par) => par.number() as i32,
+        None => match solve(&session.current_level, solver_max_states) {
+            SolveResult::Solved { moves, .. } => moves.len() as i32,
+            _ => return Err("couldnt solve it, give par a number".to_owned()),
+        },
+    };
+    let result = session.level_repository.set_par(&session.current_level.title, Some(par));
+    session.report(result)?;
+    session.terminal.tprint(format!("par for {} is {}", session.current_level.title, par));
+    Ok(())
+}
+
+fn dims(session: &mut Session, args: &[Arg]) -> Result<(), String> {
+    let (new_w, new_h) = (args[0].number(), args[1].number());
+    let level = &mut session.current_level;
+    let (old_w, old_h) = (level.w, level.h);
+    level.w = new_w as i32;
+    level.h = new_h as i32;
+    let old_tiles = level.tiles.clone();
+    level.tiles = vec![Tile::Wall; new_w.checked_mul(new_h).ok_or("level too big")? as usize];
+    for i in 0..old_w {
+        for j in 0..old_h {
+            if i < level.w && j < level.h {
+                level.tiles[(j * level.w + i) as usize] = old_tiles[(j * old_w + i) as usize];
+            }
+        }
+    }
+    // ensure entities dont go out of bounds
+    let (w, h) = (level.w, level.h);
+    level.entities.retain(|(_, x, y)| *x < w && *y < h);
+    Ok(())
+}
+
+fn link(session: &mut Session, args: &[Arg]) -> Result<(), String> {
+    let name = args[0].word();
+    if let Some(id) = session.level_repository.id_of(&name) {
+        session.place_link = id;
+    } else {
+        session.terminal.tprint(format!("level {} doesnt exist yet, link will dangle until it does", name));
+        session.place_link = name;
+    }
+    Ok(())
+}
+
+fn tokens(session: &mut Session, args: &[Arg]) -> Result<(), String> {
+    session.place_tokens = args[0].number() as i32;
+    Ok(())
+}
+
+fn profile(session: &mut Session, args: &[Arg]) -> Result<(), String> {
+    let name = args[0].word();
+    if session.profiles.select(name.clone()) {
+        session.terminal.tprint(format!("new profile {}", name));
+    } else {
+        session.terminal.tprint(format!("profile {}: {} tokens", name, session.profiles.profile().tokens()));
+    }
+    session.audio.set_volume(session.profiles.profile().volume);
+    session.profiles.save();
+    Ok(())
+}
+
+fn reset(session: &mut Session, _args: &[Arg]) -> Result<(), String> {
+    session.profiles.profile_mut().completed_levels = HashSet::new();
+    session.profiles.save();
+    Ok(())
+}
+
+fn backups(session: &mut Session, _args: &[Arg]) -> Result<(), String> {
+    let backups = list_backups().map_err(|e| format!("couldnt list backups: {}", e))?;
+    if backups.is_empty() {
+        session.terminal.tprint("no backups".to_owned());
+    }
+    for (i, name) in backups.iter().enumerate() {
+        session.terminal.tprint(format!("  {} {}", i, name));
+    }
+    Ok(())
+}
+
+fn restore(session: &mut Session, args: &[Arg]) -> Result<(), String> {
+    let arg = args[0].word();
+    // by number from backups, or by name
+    let backups = list_backups().map_err(|e| format!("couldnt list backups: {}", e))?;
+    let name = match arg.parse::<usize>() {
+        Ok(idx) => backups.get(idx),
+        Err(_) => backups.iter().find(|b| *b == &arg),
+    }.ok_or(format!("no backup {}", arg))?.clone();
+    session.level_repository = LevelRepository::restore_backup(&name).map_err(|e| format!("couldnt restore {}: {}", name, e))?;
+    for problem in session.level_repository.validate() {
+        session.terminal.tprint(problem);
+    }
+    session.terminal.tprint(format!("restored {}", name));
+    Ok(())
+}
+
+fn volume(session: &mut Session, args: &[Arg]) -> Result<(), String> {
+    let mut volume = session.profiles.profile().volume;
+    let v = args[1].number() as f32 / 100.0;
+    match args[0].word().as_str() {
+        "master" => volume.master = v,
+        "sfx" => volume.sfx = v,
+        "music" => volume.music = v,
+        channel => unreachable!("{} isnt a channel, parse checks the choice", channel),
+    }
+    session.audio.set_volume(volume);
+    session.profiles.profile_mut().volume = volume;
+    session.profiles.save();
+    Ok(())
+}
+
+fn repeat(session: &mut Session, args: &[Arg]) -> Result<(), String> {
+    let (delay, interval) = (args[0].number(), args[1].number());
+    session.profiles.profile_mut().key_repeat = KeyRepeat { delay: delay as f64 / 1000.0, interval: interval as f64 / 1000.0 };
+    session.profiles.save();
+    session.terminal.tprint(format!("key repeat after {}ms, then every {}ms", delay, interval));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use crate::terminal::*;
+    use crate::profile::*;
+
+    #[test]
+    fn parses_typed_args() {
+        let (command, args) = parse("dims 10 12").unwrap();
+        assert_eq!(command.name, "dims");
+        assert_eq!(args, vec![Arg::Number(10), Arg::Number(12)]);
+        let (_, args) = parse("par").unwrap();
+        assert!(args.is_empty());
+    }
+
+    fn error(line: &str) -> String {
+        parse(line).err().unwrap()
+    }
+
+    #[test]
+    fn argument_errors() {
+        assert_eq!(error("dims 10"), "dims expects 2 numbers: dims <w> <h>");
+        assert_eq!(error("dims 10 x"), "h should be a number, not x: dims <w> <h>");
+        assert_eq!(error("dims 0 5"), "w should be 1 to 200, not 0");
+        assert_eq!(error("dims 5 99999"), "h should be 1 to 200, not 99999");
+        assert_eq!(error("volume sfx 101"), "percent should be 0 to 100, not 101");
+        assert_eq!(error("repeat 200 5"), "interval should be at least 10, not 5");
+        assert_eq!(error("tokens 3000000000"), "tokens should be at most 2147483647, not 3000000000");
+        assert_eq!(error("volume loud 10"), "channel should be one of master, sfx, music");
+        assert_eq!(error("volume sfx"), "volume expects a choice and a number: volume <master|sfx|music> <percent>");
+        assert_eq!(error("par 1 2"), "par expects nothing or a number: par [moves]");
+        assert_eq!(error("frobnicate"), "bad command: frobnicate, try help");
+    }
+
+    #[test]
+    fn quoted_args() {
+        assert_eq!(tokenize("link \"first level\""), vec!["link", "first level"]);
+        assert_eq!(tokenize("  rename  \"a b\" c\"d e\"  "), vec!["rename", "a b", "cd e"]);
+        assert_eq!(tokenize("new \"\""), vec!["new", ""]);
+        assert_eq!(tokenize("open \"half done"), vec!["open", "half done"]);
+        let (_, args) = parse(&format!("copy {} {}", quote("first level"), quote("copied"))).unwrap();
+        assert_eq!(args, vec![Arg::Word("first level".to_owned()), Arg::Word("copied".to_owned())]);
+    }
+
+    #[test]
+    fn completes_by_argument() {
+        let levels = vec!["first level".to_owned(), "favie".to_owned(), "second".to_owned()];
+        assert_eq!(complete("re", &levels), (0, vec!["rename".to_owned(), "repeat".to_owned(), "replay".to_owned(), "reset".to_owned(), "restore".to_owned()]));
+        assert_eq!(complete("open f", &levels), (5, vec!["favie".to_owned(), "first level".to_owned()]));
+        assert_eq!(complete("link \"first l", &levels), (5, vec!["first level".to_owned()]));
+        assert_eq!(complete("volume m", &levels), (7, vec!["master".to_owned(), "music".to_owned()]));
+        assert_eq!(complete("help sa", &levels), (5, vec!["save".to_owned(), "saveas".to_owned()]));
+        // only where it makes sense
+        assert!(complete("dims 1", &levels).1.is_empty());
+        assert!(complete("nope f", &levels).1.is_empty());
+    }
+
+    #[test]
+    fn script_reports_each_bad_line() {
+        let path = temp_path("script.txt");
+        std::fs::write(&path, "new \"scratch hub\"\n\n# comments are fine\ndims 5 4\nopen \"no such level\"\ntokens lots\nlink target\n").unwrap();
+        let mut session = Session::from_parts(Terminal::new(), LevelRepository::in_memory(), ProfileRepository::in_memory(), false);
+        let target = level("target\n#####\n#pbt#\n#####");
+        session.level_repository.save_level("target".to_owned(), "santa".to_owned(), target).unwrap();
+        let failed = run_script(&mut session, &path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(failed, Ok(2));
+        assert_eq!((session.current_level.title.as_str(), session.current_level.w, session.current_level.h), ("scratch hub", 5, 4));
+        assert_eq!(session.place_link, session.level_repository.id_of(&"target".to_owned()).unwrap());
+        // scripted lines werent typed, they stay out of the history
+        assert!(session.terminal.terminal_history.is_empty());
+        let lines = &session.terminal.terminal_lines;
+        assert!(lines.contains(&format!("{}:5: level no such level not found", path)));
+        assert!(lines.contains(&format!("{}:6: tokens should be a number, not lots: tokens <tokens>", path)));
+    }
+
+    #[test]
+    fn plays_without_saving_when_locked() {
+        let mut session = Session::from_parts(Terminal::new(), LevelRepository::new_locked(), ProfileRepository::in_memory(), false);
+        assert_eq!(execute(&mut session, "play"), Ok(()));
+        assert!(session.current_instance.is_some());
+        assert!(session.terminal.terminal_lines.iter().any(|l| l.starts_with("couldnt save")));
+    }
+
+    #[test]
+    fn failed_saveas_keeps_the_title() {
+        let mut session = Session::from_parts(Terminal::new(), LevelRepository::new_locked(), ProfileRepository::in_memory(), false);
+        let title = session.current_level.title.clone();
+        assert!(execute(&mut session, "saveas elsewhere").is_err());
+        assert_eq!(session.current_level.title, title);
+    }
+
+    #[test]
+    fn names_are_unique() {
+        for (i, a) in commands.iter().enumerate() {
+            assert!(commands[i+1..].iter().all(|b| a.name != b.name), "{} twice", a.name);
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use glutin::event::VirtualKeyCode;
 
 use crate::level::*;
+use crate::rules::*;
+use crate::tween::*;
 use crate::lib::kinput::*;
 use crate::renderer::*;
 use crate::lib::kmath::*;
@@ -9,55 +11,161 @@ use crate::lib::kmath::*;
 pub struct Instance {
     pub level: LevelInstance,
     pub complete: bool,
+    pub par: Option<i32>,
+    pub autoplay: Vec<(i32, i32)>, // replaying a solution, these are the moves left
+    pub autoplay_t: f32,
+    pub replaying: bool,
+    pub portal_lock: Option<(i32, i32)>, // portal we came back out of or just used, dont go through it again until the player steps off
+    pub events: Vec<Event>, // what happened this frame, for animation and sound
+    pub tweens: Tweens,
+    pub queued: Vec<Action>, // pressed while the last move was still animating
+}
+
+pub const max_queued: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Move((i32, i32)),
+    Undo,
+    Redo,
+    Restart,
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum InstanceFrameOutcome {
     Completion(String),
-    Travel(String),
+    Travel(String, (i32, i32)),
+    Return,
     Bail,
     None,
 }
 
 impl Instance {
-    pub fn new(level_instance: LevelInstance) -> Instance {
+    pub fn new(level_instance: LevelInstance, par: Option<i32>) -> Instance {
         Instance {
             level: level_instance,
             complete: false,
+            par,
+            autoplay: Vec::new(),
+            autoplay_t: 0.0,
+            replaying: false,
+            portal_lock: None,
+            events: Vec::new(),
+            tweens: Tweens::new(),
+            queued: Vec::new(),
         }
     }
 
-    pub fn frame(&mut self, inputs: &FrameInputState, rc: &mut Vec<RenderCommand>, num_tokens: i32, t: f32) -> InstanceFrameOutcome {
-        if inputs.just_pressed(VirtualKeyCode::W) || inputs.just_pressed(VirtualKeyCode::Up) {
-            self.level.try_move((0, -1));
-        }
-        if inputs.just_pressed(VirtualKeyCode::S) || inputs.just_pressed(VirtualKeyCode::Down) {
-            self.level.try_move((0, 1));
+    pub fn new_replay(level_instance: LevelInstance, par: Option<i32>, moves: Vec<(i32, i32)>) -> Instance {
+        let mut instance = Instance::new(level_instance, par);
+        instance.autoplay = moves;
+        instance.replaying = true;
+        instance
+    }
+
+    pub fn frame(&mut self, inputs: &FrameInputState, rc: &mut Vec<RenderCommand>, num_tokens: i32, t: f32, repeat: &KeyRepeat) -> InstanceFrameOutcome {
+        if self.complete {
+            // frozen on the good job screen
+            self.level.render(inputs.screen_rect.fit_aspect_ratio(self.level.l.w as f32 / self.level.l.h as f32), rc, num_tokens, t, &self.tweens);
+            self.render_hud(inputs.screen_rect, rc);
+            let banner = inputs.screen_rect.child(0.0, 0.45, 1.0, 0.1);
+            rc.push(RenderCommand::solid_rect(banner, Vec4::new(0.0, 0.0, 0.0, 0.8), 3.0));
+            render_text_center(format!("{} - enter to continue", grade(self.level.moves(), self.par)).as_bytes(), banner.dilate_pc(-0.2), 3.5, rc);
+            if inputs.just_pressed(VirtualKeyCode::Return) || inputs.just_pressed(VirtualKeyCode::Space) {
+                return InstanceFrameOutcome::Return;
+            }
+            if inputs.just_pressed(VirtualKeyCode::Escape) {
+                return InstanceFrameOutcome::Bail;
+            }
+            return InstanceFrameOutcome::None;
         }
-        if inputs.just_pressed(VirtualKeyCode::A) || inputs.just_pressed(VirtualKeyCode::Left) {
-            self.level.try_move((-1, 0));
+
+        if self.replaying {
+            if !self.autoplay.is_empty() && t - self.autoplay_t > 0.2 && !self.tweens.busy(t) {
+                let dir = self.autoplay.remove(0);
+                self.queued.push(Action::Move(dir));
+                self.autoplay_t = t;
+            }
+        } else {
+            // holding a key repeats it, except while a slide plays out so you dont fire off more moves than you meant to
+            let sliding = self.tweens.sliding(t);
+            let pressed = |key| inputs.just_pressed(key) || (!sliding && inputs.repeated(key, repeat));
+            let mut actions = Vec::new();
+            if pressed(VirtualKeyCode::W) || pressed(VirtualKeyCode::Up) {
+                actions.push(Action::Move((0, -1)));
+            }
+            if pressed(VirtualKeyCode::S) || pressed(VirtualKeyCode::Down) {
+                actions.push(Action::Move((0, 1)));
+            }
+            if pressed(VirtualKeyCode::A) || pressed(VirtualKeyCode::Left) {
+                actions.push(Action::Move((-1, 0)));
+            }
+            if pressed(VirtualKeyCode::D) || pressed(VirtualKeyCode::Right) {
+                actions.push(Action::Move((1, 0)));
+            }
+            if pressed(VirtualKeyCode::Z) {
+                actions.push(Action::Undo);
+            }
+            if pressed(VirtualKeyCode::Y) {
+                actions.push(Action::Redo);
+            }
+            if inputs.just_pressed(VirtualKeyCode::R) {
+                actions.push(Action::Restart);
+            }
+            // dont drop keys pressed mid animation, but dont let a backlog build up either
+            for action in actions {
+                if self.queued.len() < max_queued {
+                    self.queued.push(action);
+                }
+            }
         }
-        if inputs.just_pressed(VirtualKeyCode::D) || inputs.just_pressed(VirtualKeyCode::Right) {
-            self.level.try_move((1, 0));
+
+        // one action per animation, the next goes as soon as the last one finishes
+        if !self.tweens.busy(t) && !self.queued.is_empty() {
+            match self.queued.remove(0) {
+                Action::Move(dir) => { self.level.try_move(dir); },
+                Action::Undo => { self.level.undo(); },
+                Action::Redo => { self.level.redo(); },
+                Action::Restart => { self.level.restart(); },
+            }
         }
-        if inputs.just_pressed(VirtualKeyCode::Z) {
-            self.level.undo();
+
+        self.events = self.level.take_events();
+        if !self.events.is_empty() {
+            self.tweens.start(&self.events, t);
         }
 
         // draw level
         let level_rect = inputs.screen_rect.fit_aspect_ratio(self.level.l.w as f32 / self.level.l.h as f32);
-        self.level.render(level_rect, rc, num_tokens, t);
+        self.level.render(level_rect, rc, num_tokens, t, &self.tweens);
+        self.render_hud(inputs.screen_rect, rc);
         
-        // Handle possible outcomes
+        // Handle possible outcomes, once things have got where theyre going
+        if self.tweens.busy(t) {
+            if inputs.just_pressed(VirtualKeyCode::Escape) {
+                return InstanceFrameOutcome::Bail;
+            }
+            return InstanceFrameOutcome::None;
+        }
+        if let Some((li, lj)) = self.portal_lock {
+            if !self.level.current_entities.iter().any(|(e, i, j)| *e == Entity::Player && *i == li && *j == lj) {
+                self.portal_lock = None;
+            }
+        }
         for (e1, i1, j1) in self.level.current_entities.iter() {
             for (e2, i2, j2) in self.level.current_entities.iter() {
-                if *i1 == *i2 && *j1 == *j2 {
+                if *i1 == *i2 && *j1 == *j2 && self.portal_lock != Some((*i1, *j1)) && !self.replaying {
                     match (e1, e2) {
                         (Entity::Player, Entity::Portal(tokens, dest)) => {
                             if num_tokens >= *tokens {
-                                return InstanceFrameOutcome::Travel(dest.clone())
+                                return InstanceFrameOutcome::Travel(dest.clone(), (*i1, *j1))
                             }
                         },
+                        (Entity::Player, Entity::ReturnPortal) => {
+                            // once per arrival, with nowhere to go back to it would fire every frame and block esc
+                            self.portal_lock = Some((*i1, *j1));
+                            return InstanceFrameOutcome::Return;
+                        },
                         _ => {},
                     }
                 }
@@ -65,11 +173,61 @@ impl Instance {
         }
         if self.level.victorious() && !self.complete {
             self.complete = true;
-            return InstanceFrameOutcome::Completion(self.level.l.title.clone());
+            // watching a replay doesnt count
+            if !self.replaying {
+                return InstanceFrameOutcome::Completion(self.level.l.title.clone());
+            }
         }
         if inputs.just_pressed(VirtualKeyCode::Escape) {
             return InstanceFrameOutcome::Bail;
         }
         return InstanceFrameOutcome::None;
     }
-}
\ No newline at end of file
+
+    fn render_hud(&self, screen_rect: Rect, rc: &mut Vec<RenderCommand>) {
+        let mut hud = format!("moves {}  pushes {}", self.level.moves(), self.level.pushes);
+        if let Some(par) = self.par {
+            hud.push_str(&format!("  par {}", par));
+        }
+        render_text_left(hud.as_bytes(), screen_rect.child(0.01, 0.01, 1.0, 0.04), 3.0, rc);
+    }
+}
+
+// good job / very good job for min moves
+pub fn grade(moves: i32, par: Option<i32>) -> &'static str {
+    match par {
+        Some(par) if moves <= par => "very good job",
+        _ => "good job",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    fn inputs(t: f64, keys: &[VirtualKeyCode]) -> FrameInputState {
+        let mut inputs = FrameInputState::blank();
+        inputs.t = t;
+        inputs.keys_pressed_this_frame = keys.to_vec();
+        inputs
+    }
+
+    fn frame(instance: &mut Instance, t: f64, keys: &[VirtualKeyCode]) -> InstanceFrameOutcome {
+        instance.frame(&inputs(t, keys), &mut Vec::new(), 0, t as f32, &KeyRepeat::default())
+    }
+
+    #[test]
+    fn return_portal_fires_once_per_arrival() {
+        let mut instance = Instance::new(level("ret\n#####\n#pr #\n#####").instance(), None);
+        assert_eq!(frame(&mut instance, 0.0, &[VirtualKeyCode::Right]), InstanceFrameOutcome::None);
+        assert_eq!(frame(&mut instance, 1.0, &[]), InstanceFrameOutcome::Return);
+        assert_eq!(frame(&mut instance, 1.1, &[]), InstanceFrameOutcome::None);
+        assert_eq!(frame(&mut instance, 1.2, &[VirtualKeyCode::Escape]), InstanceFrameOutcome::Bail);
+        // off and back on goes again
+        frame(&mut instance, 1.3, &[VirtualKeyCode::Right]);
+        assert_eq!(frame(&mut instance, 2.0, &[]), InstanceFrameOutcome::None);
+        frame(&mut instance, 2.1, &[VirtualKeyCode::Left]);
+        assert_eq!(frame(&mut instance, 3.0, &[]), InstanceFrameOutcome::Return);
+    }
+}
@@ -1,53 +1,63 @@
 use crate::lib::kmath::*;
 use crate::renderer::*;
+use crate::rules::*;
+use crate::tween::*;
 use serde::{Serialize, Deserialize};
+use std::fmt;
 
 
 // how am i going to do game logic and animate it?
-// store prev and new and lerp
-// allow player movement in next timesteps sure. it could be realtime lol.
+// store prev and new and lerp -> rules gives events, tween.rs lerps along them
 
-#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum Tile {
     Snow,
     Ice,
     Wall,
 }
 
-#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub enum Entity {
     Player,
     Present,
     Crate,
     Receptacle,
-    Portal(i32, String),
+    Portal(i32, String), // tokens needed, id of the level it goes to
     Pow,
     Tree,
     TreeStump,
+    ReturnPortal, // always free, takes you back where you came from
 }
 
 impl Entity {
     pub fn can_move(&self) -> bool {
         match self {
-            Entity::Receptacle | Entity::Portal(_, _) | Entity::Tree | Entity::TreeStump => false,
+            Entity::Receptacle | Entity::Portal(_, _) | Entity::ReturnPortal | Entity::Tree | Entity::TreeStump => false,
             _ => true,
         }
     }
     pub fn player_allowed(&self) -> bool {
         match self {
-            Entity::Receptacle | Entity::Portal(_, _) | Entity::Tree | Entity::TreeStump => true,
+            Entity::Receptacle | Entity::Portal(_, _) | Entity::ReturnPortal | Entity::Tree | Entity::TreeStump => true,
             _ => false,
         }
     }
     pub fn boxes_allowed(&self) -> bool {
         match self {
-            Entity::Receptacle => true,
+            Entity::Receptacle | Entity::TreeStump => true,
+            _ => false,
+        }
+    }
+    // what a pow takes out with it when it goes off
+    pub fn pow_target(&self) -> bool {
+        match self {
+            Entity::Crate | Entity::Tree => true,
             _ => false,
         }
     }
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 
 pub struct Level {
     pub title: String,
@@ -69,19 +79,35 @@ impl Level {
             entities: Vec::new(),
         }
     }
+    // title, then optional :header lines, then the grid
+    // # wall, space snow, / ice, entities are a letter: lowercase on snow, uppercase on ice
+    // anything the grid cant show (portal details, stacked entities, entities in walls) goes in the header
+    //   :portal x y tokens link    (link - for none, the cell is o/O in the grid unless its a wall)
+    //   :also x y glyph
     pub fn from_string(s: &str) -> Option<Level> {
-        let title = s.split("\n").nth(0)?.to_owned();
-        let w = s.split("\n").nth(1)?.len();
-        let h = s.split("\n").count() - 1;
+        let s = s.strip_suffix("\n").unwrap_or(s);
+        let mut it = s.split("\n");
+        let title = it.next()?.to_owned();
+        let mut header = Vec::new();
+        let mut grid = Vec::new();
+        for line in it {
+            if grid.is_empty() && line.starts_with(':') {
+                // editors like to strip trailing spaces, so nothing in the header depends on them
+                header.push(line[1..].trim_end());
+            } else {
+                grid.push(line);
+            }
+        }
+        let w = grid.first()?.len();
+        let h = grid.len();
 
-        let mut it = s.split("\n"); it.next();
-        for (line_number, line) in it.enumerate() {
+        for (line_number, line) in grid.iter().enumerate() {
             if line.len() != w {
                 println!("bad line length: line {} length is {} (w is {})", line_number, line.len(), w);
                 return None;
             }
         }
-        if h == 0 {
+        if h == 0 || w == 0 {
             println!("no level content");
             return None;
         }
@@ -94,56 +120,79 @@ impl Level {
             title: title,
         };
 
-        let mut it = s.split("\n"); it.next();
-        for (j, line) in it.enumerate() {
+        let mut portal_cells = Vec::new();
+        for (j, line) in grid.iter().enumerate() {
             for (i, c) in line.chars().enumerate() {
+                if c == 'o' || c == 'O' {
+                    portal_cells.push((i as i32, j as i32));
+                }
                 match c {
                     '#' => level.tiles[j*w + i] = Tile::Wall,
                     ' ' => level.tiles[j*w + i] = Tile::Snow,
                     '/' => level.tiles[j*w + i] = Tile::Ice,
-                    'p' => {
-                        level.tiles[j*w + i] = Tile::Snow;
-                        level.entities.push((Entity::Player, i as i32, j as i32));
-                    },
-                    'P' => {
-                        level.tiles[j*w + i] = Tile::Ice;
-                        level.entities.push((Entity::Player, i as i32, j as i32));
-                    },
-                    't' => {
-                        level.tiles[j*w + i] = Tile::Snow;
-                        level.entities.push((Entity::Receptacle, i as i32, j as i32));
-                    },
-                    'T' => {
-                        level.tiles[j*w + i] = Tile::Ice;
-                        level.entities.push((Entity::Receptacle, i as i32, j as i32));
-                    },
-                    'b' => {
-                        level.tiles[j*w + i] = Tile::Snow;
-                        level.entities.push((Entity::Present, i as i32, j as i32));
-                    },
-                    'B' => {
-                        level.tiles[j*w + i] = Tile::Ice;
-                        level.entities.push((Entity::Present, i as i32, j as i32));
-                    },
-                    'c' => {
-                        level.tiles[j*w + i] = Tile::Snow;
-                        level.entities.push((Entity::Crate, i as i32, j as i32));
-                    },
-                    'C' => {
-                        level.tiles[j*w + i] = Tile::Ice;
-                        level.entities.push((Entity::Crate, i as i32, j as i32));
-                    },
-                    _ => {
-                        println!("forbidden chars in level!");
+                    // portal is in the header
+                    'o' => level.tiles[j*w + i] = Tile::Snow,
+                    'O' => level.tiles[j*w + i] = Tile::Ice,
+                    c => {
+                        if let Some(e) = glyph_entity(c.to_ascii_lowercase()) {
+                            level.tiles[j*w + i] = if c.is_ascii_uppercase() { Tile::Ice } else { Tile::Snow };
+                            level.entities.push((e, i as i32, j as i32));
+                        } else {
+                            println!("forbidden chars in level!");
+                            return None;
+                        }
+                    }
+                }
+            }
+        }
+
+        for line in header {
+            let parts: Vec<&str> = line.splitn(5, ' ').collect();
+            let pos = (parts.get(1)?.parse::<i32>().ok()?, parts.get(2)?.parse::<i32>().ok()?);
+            if pos.0 < 0 || pos.1 < 0 || pos.0 >= level.w || pos.1 >= level.h {
+                println!("header entity out of bounds: {}", line);
+                return None;
+            }
+            match parts[0] {
+                "portal" => {
+                    // marked o in the grid, unless its hidden in a wall
+                    if !portal_cells.contains(&pos) && level.tiles[(pos.1 * level.w + pos.0) as usize] != Tile::Wall {
+                        println!("portal not marked in the grid: {}", line);
                         return None;
                     }
+                    let tokens = parts.get(3)?.parse::<i32>().ok()?;
+                    // - for a portal that doesnt go anywhere yet
+                    let link = match parts.get(4) {
+                        None | Some(&"-") => String::new(),
+                        Some(link) => link.to_string(),
+                    };
+                    level.entities.push((Entity::Portal(tokens, link), pos.0, pos.1));
+                },
+                "also" => {
+                    let e = parts.get(3)?.chars().next().and_then(glyph_entity);
+                    if e.is_none() || parts.len() != 4 {
+                        println!("bad header line: {}", line);
+                        return None;
+                    }
+                    level.entities.push((e.unwrap(), pos.0, pos.1));
+                },
+                _ => {
+                    println!("bad header line: {}", line);
+                    return None;
                 }
             }
         }
+        for (i, j) in portal_cells {
+            if !level.entities.iter().any(|(e, ei, ej)| matches!(e, Entity::Portal(_, _)) && *ei == i && *ej == j) {
+                println!("o at {},{} but no :portal line for it", i, j);
+                return None;
+            }
+        }
+
         Some(level)
     }
 
-    fn victorious(w: i32, h: i32, tiles: &[Tile], entities: &[(Entity, i32, i32)]) -> bool {
+    pub fn victorious(w: i32, h: i32, tiles: &[Tile], entities: &[(Entity, i32, i32)]) -> bool {
         entities.iter().any(|(e, i, j)| *e == Entity::Receptacle) &&
         entities.iter().all(|(e, i, j)| match e {
             Entity::Receptacle => {
@@ -161,7 +210,7 @@ impl Level {
     }
 
     pub fn render(&self, level_rect: Rect, rc: &mut Vec<RenderCommand>, num_tokens: i32, t: f32) {
-        render(level_rect, rc, self.w, self.h, &self.tiles, &self.entities, num_tokens, t)
+        render(level_rect, rc, self.w, self.h, &self.tiles, &self.entities, num_tokens, t, &|_, _, _| Vec2::new(0.0, 0.0))
     }
 
     pub fn aspect(&self) -> f32 {
@@ -169,121 +218,130 @@ impl Level {
     }
 
     pub fn instance(&self) -> LevelInstance {
-        LevelInstance { l: self.clone(), current_entities: self.entities.clone(), history: Vec::new(), momentum: Vec::new() }
+        LevelInstance { l: self.clone(), current_entities: self.entities.clone(), path: Vec::new(), pushes: 0, history: Vec::new(), future: Vec::new(), events: Vec::new() }
     }
 }
 
+// what undo and redo go back and forth between
+#[derive(Clone)]
+pub struct Snapshot {
+    pub entities: Vec<(Entity, i32, i32)>,
+    pub path: Vec<(i32, i32)>,
+    pub pushes: i32,
+    pub events: Vec<Event>, // what happened going from this one to the next
+}
+
 pub struct LevelInstance {
     pub l: Level,
     pub current_entities: Vec<(Entity, i32, i32)>,
-    pub history: Vec<Vec<(Entity, i32, i32)>>,
-    pub momentum: Vec<((i32, i32), (i32, i32))>,
+    pub path: Vec<(i32, i32)>, // moves that got us here, minus undone ones
+    pub pushes: i32, // moves where something other than a player moved
+    pub history: Vec<Snapshot>,
+    pub future: Vec<Snapshot>, // undone states for redo, gone as soon as a new move is made
+    pub events: Vec<Event>, // since the last take_events
 }
 
 impl LevelInstance {
 
-    // this logic is kind of fucked lmao
-    pub fn accept_move(&self, dir: (i32, i32), pos: (i32, i32)) -> bool {
-        // let movers = self.current_entities
-        // maybe I should have static entities as a separate thing
-        let candidate_pos = (dir.0 + pos.0, dir.1 + pos.1);
-        if self.l.tiles[(self.l.w * candidate_pos.1 + candidate_pos.0) as usize] == Tile::Wall {
-            return false;
-        }
-        for e in self.current_entities.iter().filter_map(|(e, i, j)| if pos.0 == *i && pos.1 == *j { Some(e.clone()) } else { None }) {
-            if e.can_move() {
-                if e == Entity::Player && !self.current_entities.iter().any(|(e, i, j)| candidate_pos.0 == *i && candidate_pos.1 == *j && !e.player_allowed()) {
-                    return true;
-                } else if !self.current_entities.iter().any(|(e, i, j)| candidate_pos.0 == *i && candidate_pos.1 == *j && !e.boxes_allowed()) {
-                    return true;
-                } else {
-                    return self.accept_move(dir, candidate_pos);        
-                }
-            }
+    pub fn try_move(&mut self, dir: (i32, i32)) -> bool {
+        // only make history if move actually gets done
+        let (entities, events) = match step(&self.l, &self.current_entities, dir) {
+            Some(result) => result,
+            None => return false,
+        };
+
+        self.history.push(self.snapshot(events.clone()));
+        self.future.clear();
+        self.current_entities = entities;
+        self.path.push(dir);
+        if events.iter().any(|ev| match ev {
+            Event::Stepped { .. } | Event::StartedSliding { .. } | Event::Stopped { .. } | Event::Delivered { .. } | Event::EnteredPortal { .. } => false,
+            Event::Pushed { entity, .. } | Event::Slid { entity, .. } => *entity != Entity::Player,
+            _ => true,
+        }) {
+            self.pushes += 1;
         }
+        self.events.extend(events);
+
         return true;
     }
 
-    pub fn apply_move(&mut self, dir: (i32, i32), pos: (i32, i32)) {
-        let candidate_pos = (dir.0 + pos.0, dir.1 + pos.1);
-        for i in 0..self.current_entities.len() {
-            let (e, i, j) = &self.current_entities[i];
-            // if we can move, first push the thing where we are moving
-            if *i == candidate_pos.0 && *j == candidate_pos.1 && e.can_move() {
-                // so this shouldnt even be happening when player moves up into nothing
-                self.apply_move(dir, candidate_pos);        
-                // if *e == Entity::Player && !self.current_entities.iter().any(|(e, i, j)| candidate_pos.0 == *i && candidate_pos.1 == *j && !e.player_allowed()) {
-                // } else if !self.current_entities.iter().any(|(e, i, j)| candidate_pos.0 == *i && candidate_pos.1 == *j && !e.boxes_allowed()) {
-                //     self.apply_move(dir, candidate_pos);        
-                // }
-            }
-        }
-        // do actual moving
-        for e in self.current_entities.iter_mut() {
-            if e.0.can_move() && e.1 == pos.0 && e.2 == pos.1 {
-                *e = (e.0.clone(), candidate_pos.0, candidate_pos.1);
-                if self.l.tiles[(candidate_pos.1 * self.l.w + candidate_pos.0) as usize] == Tile::Ice {
-                    self.momentum.push((candidate_pos, dir));
-                }
-            }
+    fn snapshot(&self, events: Vec<Event>) -> Snapshot {
+        Snapshot {
+            entities: self.current_entities.clone(),
+            path: self.path.clone(),
+            pushes: self.pushes,
+            events,
         }
     }
 
-    pub fn try_move(&mut self, dir: (i32, i32)) -> bool {
-        self.momentum.clear();
-        // return if move actually gets done
-        // only make history if move actually gets done
-        // move gets accepted if theres eventually an empty space
-        let moving_players: Vec<usize> = self.current_entities.iter()
-            .enumerate()
-            .filter(|(idx, (e, i, j))| match e {Entity::Player => true, _ => false})
-            .filter(|(idx, (e, i, j))| self.accept_move(dir, (*i, *j)))
-            .map(|(idx, (e, i, j))| idx)
-            .collect();
-        
-        if moving_players.len() == 0 {
+    fn restore(&mut self, s: Snapshot) {
+        self.current_entities = s.entities;
+        self.path = s.path;
+        self.pushes = s.pushes;
+    }
+
+    pub fn undo(&mut self) -> bool {
+        if self.history.len() == 0 {
             return false;
         }
 
-        self.history.push(self.current_entities.clone());
+        let prev = self.history.pop().unwrap();
+        self.events.push(Event::Undo);
+        self.events.extend(prev.events.iter().rev().filter_map(|ev| ev.reversed()));
+        self.future.push(self.snapshot(prev.events.clone()));
+        self.restore(prev);
 
-        for mp in moving_players {
-            self.apply_move(dir, (self.current_entities[mp].1, self.current_entities[mp].2));
-        }
+        return true;
+    }
 
-        while self.momentum.len() != 0 {
-            let mm_copy = self.momentum.clone();
-            self.momentum.clear();
-            for (mpos, mdir) in mm_copy {
-                if self.accept_move(mdir, mpos) {
-                    self.apply_move(mdir, mpos);
-                }
-            }
+    pub fn redo(&mut self) -> bool {
+        if self.future.len() == 0 {
+            return false;
         }
 
+        let next = self.future.pop().unwrap();
+        self.events.push(Event::Redo);
+        self.events.extend(next.events.iter().cloned());
+        self.history.push(self.snapshot(next.events.clone()));
+        self.restore(next);
+
         return true;
     }
 
-    pub fn undo(&mut self) -> bool {
-        if self.history.len() == 0 {
+    // back to the start, but as a move so it can be undone
+    pub fn restart(&mut self) -> bool {
+        if self.current_entities == self.l.entities {
             return false;
         }
 
-        self.current_entities = self.history.pop().unwrap();
+        self.history.push(self.snapshot(Vec::new()));
+        self.future.clear();
+        self.restore(Snapshot { entities: self.l.entities.clone(), path: Vec::new(), pushes: 0, events: Vec::new() });
+        self.events.push(Event::Restart);
 
         return true;
     }
 
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
+    pub fn moves(&self) -> i32 {
+        self.path.len() as i32
+    }
+
     pub fn victorious(&self) -> bool {
         Level::victorious(self.l.w, self.l.h, &self.l.tiles, &self.current_entities)
     }
 
-    pub fn render(&self, level_rect: Rect, rc: &mut Vec<RenderCommand>, num_tokens: i32, t: f32) {
-        render(level_rect, rc, self.l.w, self.l.h, &self.l.tiles, &self.current_entities, num_tokens, t)
+    pub fn render(&self, level_rect: Rect, rc: &mut Vec<RenderCommand>, num_tokens: i32, t: f32, tweens: &Tweens) {
+        render(level_rect, rc, self.l.w, self.l.h, &self.l.tiles, &self.current_entities, num_tokens, t, &|e, i, j| tweens.offset(e, i, j, t))
     }
 }
 
-fn render(level_rect: Rect, rc: &mut Vec<RenderCommand>, w: i32, h: i32, tiles: &[Tile], entities: &[(Entity, i32, i32)], num_tokens: i32, t: f32) {
+// offset is where to draw an entity relative to its cell, in cells, for things partway through moving
+fn render(level_rect: Rect, rc: &mut Vec<RenderCommand>, w: i32, h: i32, tiles: &[Tile], entities: &[(Entity, i32, i32)], num_tokens: i32, t: f32, offset: &dyn Fn(&Entity, i32, i32) -> Vec2) {
     for i in 0..w {
         for j in 0..h {
             rc.push(RenderCommand {
@@ -295,7 +353,9 @@ fn render(level_rect: Rect, rc: &mut Vec<RenderCommand>, w: i32, h: i32, tiles:
         }
     }
     for (e, i, j) in entities.iter() {
+        let off = offset(e, *i, *j);
         let tile_rect = level_rect.grid_child(*i, *j, w, h);
+        let tile_rect = tile_rect.translate(Vec2::new(off.x * tile_rect.w, off.y * tile_rect.h));
         match e {
             Entity::Portal(tokens, link) => {
                 rc.push(RenderCommand {
@@ -320,24 +380,19 @@ fn render(level_rect: Rect, rc: &mut Vec<RenderCommand>, w: i32, h: i32, tiles:
                     render_text_center(format!("{}", tokens).as_bytes(), num_rect.dilate_pc(-0.1), 2.0, rc);
                 } else {
                     // yea todo animate
-                    let mut colour = Vec4::new(0.5, 0.0, 0.5, 1.0);
-                    let final_colour = Vec4::new(0.8, 0.8, 0.5, 1.0);
-                    let mut w = 0.7;
-                    let mut h = 0.8;
-                    let mut rect = portal_rect;
-                    let mut depth = 1.2;
-                    for x in 0..6 {
-                        rc.push(RenderCommand::solid_rect(rect, colour, depth));
-                        let cx = remap(noise1d(t/4., 123 * *i as u32 + *j as u32 + x * 237621), 0., 1., 0., 1.-w);
-                        let cy = remap(noise1d(t/4., 456 * *i as u32 + *j as u32 + x * 981241), 0., 1., 0., 1.-h);
-                        rect = rect.child(cx, cy, w, h);
-                        w *= 0.95;
-                        h *= 0.95;
-                        depth += 0.1;
-                        colour = colour.lerp(final_colour, 0.1);
-                    }
+                    render_swirl(portal_rect, Vec4::new(0.5, 0.0, 0.5, 1.0), Vec4::new(0.8, 0.8, 0.5, 1.0), *i, *j, t, rc);
                 }
             },
+            Entity::ReturnPortal => {
+                rc.push(RenderCommand {
+                    colour: Vec4::new(1.0, 1.0, 1.0, 1.0),
+                    sprite_clip: entity_clip(e),
+                    pos: tile_rect,
+                    depth: 2.5,
+                });
+                let portal_rect = tile_rect.child(3./16., 3./16., 10./16., 13./16.);
+                render_swirl(portal_rect, Vec4::new(0.0, 0.2, 0.5, 1.0), Vec4::new(0.5, 0.8, 0.8, 1.0), *i, *j, t, rc);
+            },
             Entity::Player => {
 
                 // can rc even flip sprites
@@ -378,10 +433,10 @@ fn render(level_rect: Rect, rc: &mut Vec<RenderCommand>, w: i32, h: i32, tiles:
             _ => rc.push(RenderCommand {
                 colour: Vec4::new(1.0, 1.0, 1.0, 1.0),
                 sprite_clip: entity_clip(e),
-                pos: level_rect.grid_child(*i, *j, w, h),
+                pos: tile_rect,
                 depth: match e {
                     Entity::Player | Entity::Present | Entity::Crate | Entity::Pow => 2.0,
-                    Entity::Receptacle | Entity::Portal(_, _) => 1.5,
+                    Entity::Receptacle | Entity::Portal(_, _) | Entity::ReturnPortal | Entity::TreeStump => 1.5,
                     _ => 1.0,
                 },
             }),
@@ -389,6 +444,103 @@ fn render(level_rect: Rect, rc: &mut Vec<RenderCommand>, w: i32, h: i32, tiles:
     }
 }
 
+fn render_swirl(portal_rect: Rect, mut colour: Vec4, final_colour: Vec4, i: i32, j: i32, t: f32, rc: &mut Vec<RenderCommand>) {
+    let mut w = 0.7;
+    let mut h = 0.8;
+    let mut rect = portal_rect;
+    let mut depth = 1.2;
+    for x in 0..6 {
+        rc.push(RenderCommand::solid_rect(rect, colour, depth));
+        let cx = remap(noise1d(t/4., 123 * i as u32 + j as u32 + x * 237621), 0., 1., 0., 1.-w);
+        let cy = remap(noise1d(t/4., 456 * i as u32 + j as u32 + x * 981241), 0., 1., 0., 1.-h);
+        rect = rect.child(cx, cy, w, h);
+        w *= 0.95;
+        h *= 0.95;
+        depth += 0.1;
+        colour = colour.lerp(final_colour, 0.1);
+    }
+}
+
+fn entity_glyph(e: &Entity) -> Option<char> {
+    match e {
+        Entity::Player => Some('p'),
+        Entity::Receptacle => Some('t'),
+        Entity::Present => Some('b'),
+        Entity::Crate => Some('c'),
+        Entity::Pow => Some('x'),
+        Entity::Tree => Some('y'),
+        Entity::TreeStump => Some('v'),
+        Entity::ReturnPortal => Some('r'),
+        Entity::Portal(_, _) => None,
+    }
+}
+
+fn glyph_entity(c: char) -> Option<Entity> {
+    match c {
+        'p' => Some(Entity::Player),
+        't' => Some(Entity::Receptacle),
+        'b' => Some(Entity::Present),
+        'c' => Some(Entity::Crate),
+        'x' => Some(Entity::Pow),
+        'y' => Some(Entity::Tree),
+        'v' => Some(Entity::TreeStump),
+        'r' => Some(Entity::ReturnPortal),
+        _ => None,
+    }
+}
+
+// inverse of from_string
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut header = Vec::new();
+        let mut grid = String::new();
+        // parsing gives grid entities in reading order then the header ones, so to get the same order back
+        // the grid can only show the leading run of entities that are already in reading order
+        // portal cells are always an o, so nothing else gets drawn on one
+        let portal_at = |i: i32, j: i32| self.entities.iter().any(|(e, ei, ej)| *ei == i && *ej == j && matches!(e, Entity::Portal(_, _)));
+        let mut in_grid = 0;
+        let mut last = None;
+        for (e, i, j) in self.entities.iter() {
+            let ok = entity_glyph(e).is_some() && self.tiles[(j * self.w + i) as usize] != Tile::Wall && !portal_at(*i, *j) && last.is_none_or(|l| (*j, *i) > l);
+            if !ok {
+                break;
+            }
+            last = Some((*j, *i));
+            in_grid += 1;
+        }
+        for j in 0..self.h {
+            grid.push('\n');
+            for i in 0..self.w {
+                let tile = self.tiles[(j * self.w + i) as usize];
+                let glyph = self.entities[..in_grid].iter().find(|(_, ei, ej)| *ei == i && *ej == j).and_then(|(e, _, _)| entity_glyph(e));
+                let c = if let Some(g) = glyph {
+                    g
+                } else if tile != Tile::Wall && portal_at(i, j) {
+                    'o'
+                } else {
+                    match tile {
+                        Tile::Wall => '#',
+                        Tile::Snow => ' ',
+                        Tile::Ice => '/',
+                    }
+                };
+                grid.push(if tile == Tile::Ice { c.to_ascii_uppercase() } else { c });
+            }
+        }
+        for (e, i, j) in self.entities[in_grid..].iter() {
+            match e {
+                Entity::Portal(tokens, link) => header.push(format!(":portal {} {} {} {}", i, j, tokens, if link.is_empty() { "-" } else { link })),
+                e => header.push(format!(":also {} {} {}", i, j, entity_glyph(e).unwrap())),
+            }
+        }
+        write!(f, "{}", self.title)?;
+        for line in header {
+            write!(f, "\n{}", line)?;
+        }
+        write!(f, "{}", grid)
+    }
+}
+
 pub fn tile_clip(t: Tile) -> Rect {
     match t {
         Tile::Snow => Rect::new(1.0, 0.0, 1.0, 1.0),
@@ -403,9 +555,95 @@ pub fn entity_clip(e: &Entity) -> Rect {
         Entity::Present => Rect::new(3.0, 0.0, 1.0, 1.0),
         Entity::Receptacle => Rect::new(5.0, 0.0, 1.0, 1.0),
         Entity::Crate => Rect::new(6.0, 0.0, 1.0, 1.0),
-        Entity::Portal(_,_) => Rect::new(7.0, 1.0, 1.0, 1.0),
+        Entity::Portal(_,_) | Entity::ReturnPortal => Rect::new(7.0, 1.0, 1.0, 1.0),
         Entity::Pow => Rect::new(6.0, 1.0, 1.0, 1.0),
         Entity::Tree => Rect::new(7.0, 2.0, 1.0, 1.0),
         Entity::TreeStump => Rect::new(5.0, 2.0, 1.0, 1.0),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use crate::manifest::*;
+    use crate::level_repository::*;
+
+    fn count(li: &LevelInstance, e: Entity) -> usize {
+        li.current_entities.iter().filter(|(x, _, _)| *x == e).count()
+    }
+
+    fn round_trips(level: &Level) {
+        assert_eq!(Level::from_string(&level.to_string()).as_ref(), Some(level), "{} doesnt survive to_string:\n{}", level.title, level);
+    }
+
+    #[test]
+    fn to_string_round_trips() {
+        for s in noice_levels.iter().chain(ice_levels).chain(crate_levels) {
+            round_trips(&level(s));
+        }
+        let repo = LevelRepository::load(levels_path).expect("couldnt load levels.dat");
+        for meta in repo.data.values() {
+            round_trips(&repo.get_level_by_id(&meta.id).unwrap());
+        }
+        // out of reading order, stacked, and a portal with nowhere to go
+        let mut l = level("mixed\n#####\n#p/b#\n#####");
+        l.entities.reverse();
+        l.entities.push((Entity::Portal(2, String::new()), 2, 1));
+        l.entities.push((Entity::Receptacle, 3, 1));
+        round_trips(&l);
+        assert!(l.to_string().lines().all(|line| !line.ends_with(' ')));
+    }
+
+    #[test]
+    fn portals_match_the_grid() {
+        assert!(Level::from_string("ok\n:portal 2 1 0 -\n#####\n#po #\n#####").is_some());
+        assert!(Level::from_string("no line\n#####\n#po #\n#####").is_none());
+        assert!(Level::from_string("no o\n:portal 3 1 0 -\n#####\n#po #\n#####").is_none());
+    }
+
+    #[test]
+    fn undo_and_redo_detonation() {
+        let mut li = level("pow\n#######\n#px c #\n#######").instance();
+        li.try_move((1, 0));
+        li.try_move((1, 0));
+        assert_eq!((count(&li, Entity::Pow), count(&li, Entity::Crate)), (0, 0));
+        assert!(li.undo());
+        assert_eq!((count(&li, Entity::Pow), count(&li, Entity::Crate)), (1, 1));
+        assert!(li.redo());
+        assert_eq!((count(&li, Entity::Pow), count(&li, Entity::Crate)), (0, 0));
+    }
+
+    #[test]
+    fn felling_counts_as_a_push() {
+        let mut li = level("tree\n#######\n#pb//y#\n#######").instance();
+        assert!(li.try_move((1, 0)));
+        assert_eq!(li.pushes, 1);
+    }
+
+    #[test]
+    fn restart_is_undoable() {
+        let mut li = level(noice_levels[0]).instance();
+        li.try_move((0, -1));
+        li.try_move((-1, 0));
+        assert!(li.restart());
+        assert_eq!(li.moves(), 0);
+        assert!(li.undo());
+        assert_eq!(li.moves(), 2);
+    }
+
+    #[test]
+    fn undo_plays_the_move_backwards() {
+        let mut li = level("undo\n######\n#pb  #\n######").instance();
+        li.try_move((1, 0));
+        li.take_events();
+        li.undo();
+        assert_eq!(li.take_events(), vec![
+            Event::Undo,
+            Event::Pushed { entity: Entity::Present, from: (3, 1), to: (2, 1), tick: 0 },
+            Event::Stepped { from: (2, 1), to: (1, 1), tick: 0 },
+        ]);
+        li.redo();
+        assert_eq!(li.take_events()[0], Event::Redo);
+    }
+}
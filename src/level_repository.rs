@@ -1,33 +1,131 @@
 use std::collections::HashMap;
 use crate::level::*;
+use crate::replay::*;
 use serde::{Serialize, Deserialize};
 
+use std::fmt;
+use std::fs;
 use std::fs::File;
+use std::io;
 use std::io::Read;
 use std::io::Write;
-
-// maybe overwrite protection or something would be smart
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 pub const levels_path: &str = "./levels.dat";
+pub const backups_path: &str = "./backups";
+pub const backups_kept: usize = 10;
+pub const backup_interval: Duration = Duration::from_secs(5 * 60); // play saves every solve, dont let that rotate the backups away
+
+#[derive(Debug)]
+pub enum LevelRepositoryError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+    Locked, // levels file didnt load, so we wont clobber it
+    Missing(String),
+    Exists(String),
+    NoBackup(String),
+}
+
+impl fmt::Display for LevelRepositoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LevelRepositoryError::Io(e) => write!(f, "io error: {}", e),
+            LevelRepositoryError::Parse(e) => write!(f, "bad levels file: {}", e),
+            LevelRepositoryError::Locked => write!(f, "levels file failed to load, not overwriting it"),
+            LevelRepositoryError::Missing(name) => write!(f, "level {} not found", name),
+            LevelRepositoryError::Exists(name) => write!(f, "level {} already exists", name),
+            LevelRepositoryError::NoBackup(name) => write!(f, "no backup {}", name),
+        }
+    }
+}
+
+impl From<io::Error> for LevelRepositoryError {
+    fn from(e: io::Error) -> Self {
+        LevelRepositoryError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for LevelRepositoryError {
+    fn from(e: serde_json::Error) -> Self {
+        LevelRepositoryError::Parse(e)
+    }
+}
+
+impl LevelRepositoryError {
+    pub fn is_missing_file(&self) -> bool {
+        match self {
+            LevelRepositoryError::Io(e) => e.kind() == io::ErrorKind::NotFound,
+            _ => false,
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct LevelRepository {
     pub data: HashMap<String, LevelMetadata>,
+    #[serde(skip)]
+    locked: bool,
+    #[serde(skip)]
+    path: Option<String>, // where it saves to, none just keeps it in memory
+    #[serde(skip)]
+    last_backup: Option<Instant>,
 }
 
 impl LevelRepository {
-    pub fn load(path: &str) -> Option<LevelRepository> {
+    pub fn load(path: &str) -> Result<LevelRepository, LevelRepositoryError> {
         print!("attempting to load levels.dat file...");
-        let mut levels_file = File::open(path).ok()?;
+        let mut levels_file = File::open(path)?;
         let mut contents = String::new();
-        levels_file.read_to_string(&mut contents).ok();
-        let repo = serde_json::from_str(&contents).ok()?;
+        levels_file.read_to_string(&mut contents)?;
+        let mut repo: LevelRepository = serde_json::from_str(&contents)?;
+        repo.path = Some(path.to_owned());
         println!("ok");
-        Some(repo)
+        repo.assign_missing_ids();
+        Ok(repo)
+    }
+    // levels from before ids existed get one from their name, so its the same every load even if it never gets saved
+    fn assign_missing_ids(&mut self) {
+        let mut unnamed: Vec<String> = self.data.iter().filter(|(_, md)| md.id.is_empty()).map(|(k, _)| k.clone()).collect();
+        unnamed.sort();
+        for name in unnamed {
+            // fnv-1a, std's hasher can change between versions
+            let hash = name.bytes().fold(0xcbf29ce484222325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3));
+            let id = (0..).map(|n| format!("{:016x}-{:04x}", hash, n)).find(|id| !self.data.values().any(|md| md.id == *id)).unwrap();
+            self.data.get_mut(&name).unwrap().id = id;
+        }
     }
     pub fn new() -> LevelRepository {
         LevelRepository {
             data: HashMap::new(),
+            locked: false,
+            path: Some(levels_path.to_owned()),
+            last_backup: None,
+        }
+    }
+    // for when the levels file is there but broken, can play and edit but never save over it
+    pub fn new_locked() -> LevelRepository {
+        LevelRepository {
+            data: HashMap::new(),
+            locked: true,
+            path: None,
+            last_backup: None,
+        }
+    }
+    #[cfg(test)]
+    pub fn in_memory() -> LevelRepository {
+        LevelRepository {
+            data: HashMap::new(),
+            locked: false,
+            path: None,
+            last_backup: None,
+        }
+    }
+    // checked before changing anything, so whats in memory never gets ahead of the file
+    fn unlocked(&self) -> Result<(), LevelRepositoryError> {
+        if self.locked {
+            Err(LevelRepositoryError::Locked)
+        } else {
+            Ok(())
         }
     }
     pub fn contains_level(&self, name: &String) -> bool{
@@ -36,21 +134,185 @@ impl LevelRepository {
     pub fn get_level(&self, name: &String) -> Option<Level> {
         self.data.get(name).map(|x| x.level.clone())
     }
-    pub fn save_level(&mut self, name: String, creator: String, level: Level) {
+    pub fn get_level_by_id(&self, id: &String) -> Option<Level> {
+        self.data.values().find(|md| md.id == *id).map(|x| x.level.clone())
+    }
+    // portal links are ids, but ones placed before their level existed are still a name
+    pub fn get_linked_level(&self, link: &String) -> Option<Level> {
+        self.get_level_by_id(link).or(self.get_level(link))
+    }
+    pub fn par_of(&self, name: &String) -> Option<i32> {
+        self.data.get(name).and_then(|x| x.par)
+    }
+    pub fn set_par(&mut self, name: &String, par: Option<i32>) -> Result<Vec<String>, LevelRepositoryError> {
+        self.unlocked()?;
+        self.data.get_mut(name).ok_or(LevelRepositoryError::Missing(name.clone()))?.par = par;
+        self.write()
+    }
+    pub fn solution_of(&self, name: &String) -> Option<String> {
+        self.data.get(name).and_then(|x| x.solution.clone())
+    }
+    // keeps the shortest one, returns whether it was an improvement
+    pub fn record_solution(&mut self, name: &String, moves: String) -> Result<bool, LevelRepositoryError> {
+        self.unlocked()?;
+        let md = self.data.get_mut(name).ok_or(LevelRepositoryError::Missing(name.clone()))?;
+        if md.solution.as_ref().map(|s| s.len() <= moves.len()).unwrap_or(false) {
+            return Ok(false);
+        }
+        md.solution = Some(moves);
+        self.write()?;
+        Ok(true)
+    }
+    pub fn id_of(&self, name: &String) -> Option<String> {
+        self.data.get(name).map(|x| x.id.clone())
+    }
+    // what profiles remember a level by: its id, or the title for manifest levels that arent in here
+    pub fn progress_key(&self, name: &String) -> String {
+        self.id_of(name).unwrap_or(name.clone())
+    }
+    fn new_level_id(&self) -> String {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+        let mut n = self.data.len() as u128;
+        loop {
+            let id = format!("{:016x}-{:04x}", nanos, n);
+            if !self.data.values().any(|md| md.id == id) {
+                return id;
+            }
+            n += 1;
+        }
+    }
+    // points portal links at ids where it can, returns complaints about the ones that go nowhere
+    pub fn validate(&mut self) -> Vec<String> {
+        let ids: HashMap<String, String> = self.data.iter().map(|(k, md)| (k.clone(), md.id.clone())).collect();
+        let mut problems = Vec::new();
+        let mut names: Vec<&String> = self.data.keys().collect();
+        names.sort();
+        let names: Vec<String> = names.into_iter().cloned().collect();
+        for name in names {
+            let md = self.data.get_mut(&name).unwrap();
+            for (e, i, j) in md.level.entities.iter_mut() {
+                if let Entity::Portal(tokens, link) = e {
+                    if ids.values().any(|id| id == link) {
+                        continue;
+                    }
+                    if let Some(id) = ids.get(link) {
+                        *e = Entity::Portal(*tokens, id.clone());
+                    } else {
+                        problems.push(format!("{}: portal at {},{} links to missing level {}", name, i, j, link));
+                    }
+                }
+            }
+        }
+        problems
+    }
+    pub fn save_level(&mut self, name: String, creator: String, level: Level) -> Result<Vec<String>, LevelRepositoryError> {
+        self.unlocked()?;
         let date: String = chrono::offset::Local::now().to_string();
+        let id = self.id_of(&name).unwrap_or(self.new_level_id());
+        let par = self.par_of(&name);
+        let mut problems = Vec::new();
+        let solution = self.solution_of(&name);
+        let solution = solution.filter(|moves| solves(&level, moves));
+        if solution.is_none() && self.solution_of(&name).is_some() {
+            problems.push(format!("{}: recorded solution doesnt work any more, dropped it", name));
+        }
         let md = LevelMetadata {
+            id,
+            par,
+            solution,
             creator,
             date,
             level,
         };
         self.data.insert(name, md);
-
-        // save levels to filesystem
-        let str = serde_json::to_string(self).unwrap();
-        let mut f = File::create(levels_path);
-        if f.is_ok() {
-            f.unwrap().write_all(str.as_bytes());
+        problems.extend(self.write()?);
+        Ok(problems)
+    }
+    // saves a bunch at once under their titles, skipping names already taken. returns the ones that got in
+    pub fn import_levels(&mut self, levels: Vec<Level>, creator: String) -> Result<(Vec<String>, Vec<String>), LevelRepositoryError> {
+        self.unlocked()?;
+        let date: String = chrono::offset::Local::now().to_string();
+        let mut imported = Vec::new();
+        for level in levels {
+            if self.data.contains_key(&level.title) {
+                continue;
+            }
+            let md = LevelMetadata {
+                id: self.new_level_id(),
+                par: None,
+                solution: None,
+                creator: creator.clone(),
+                date: date.clone(),
+                level,
+            };
+            imported.push(md.level.title.clone());
+            self.data.insert(md.level.title.clone(), md);
+        }
+        let problems = self.write()?;
+        Ok((imported, problems))
+    }
+    // id stays the same so portals keep working, links still by name get pointed at the id
+    // profiles go by id too so nobody loses progress
+    pub fn rename_level(&mut self, old: &String, new: String) -> Result<Vec<String>, LevelRepositoryError> {
+        self.unlocked()?;
+        if self.data.contains_key(&new) {
+            return Err(LevelRepositoryError::Exists(new));
+        }
+        let mut md = self.data.remove(old).ok_or(LevelRepositoryError::Missing(old.clone()))?;
+        md.level.title = new.clone();
+        let id = md.id.clone();
+        self.data.insert(new, md);
+        for md in self.data.values_mut() {
+            for (e, _, _) in md.level.entities.iter_mut() {
+                if let Entity::Portal(tokens, link) = e {
+                    if link == old {
+                        *e = Entity::Portal(*tokens, id.clone());
+                    }
+                }
+            }
+        }
+        self.write()
+    }
+    pub fn delete_level(&mut self, name: &String) -> Result<Vec<String>, LevelRepositoryError> {
+        self.unlocked()?;
+        self.data.remove(name).ok_or(LevelRepositoryError::Missing(name.clone()))?;
+        self.write()
+    }
+    pub fn copy_level(&mut self, src: &String, dest: String, creator: String) -> Result<Vec<String>, LevelRepositoryError> {
+        if self.data.contains_key(&dest) {
+            return Err(LevelRepositoryError::Exists(dest));
         }
+        let mut level = self.get_level(src).ok_or(LevelRepositoryError::Missing(src.clone()))?;
+        level.title = dest.clone();
+        self.save_level(dest, creator, level)
+    }
+    // save levels to filesystem
+    fn write(&mut self) -> Result<Vec<String>, LevelRepositoryError> {
+        self.unlocked()?;
+        let problems = self.validate();
+        if let Some(path) = &self.path {
+            let str = serde_json::to_string(self)?;
+            if self.last_backup.is_none_or(|t| t.elapsed() >= backup_interval) {
+                backup_levels(path)?;
+                self.last_backup = Some(Instant::now());
+            }
+            write_atomic(path, &str)?;
+        }
+        Ok(problems)
+    }
+    // puts a backup back as levels.dat (backing up whats there first) and returns whats in it
+    pub fn restore_backup(name: &str) -> Result<LevelRepository, LevelRepositoryError> {
+        // only ever one we listed, the name goes into a path
+        if !list_backups()?.iter().any(|b| b == name) {
+            return Err(LevelRepositoryError::NoBackup(name.to_owned()));
+        }
+        let backup = format!("{}/{}.dat", backups_path, name);
+        let mut repo = LevelRepository::load(&backup)?;
+        let contents = fs::read_to_string(&backup)?;
+        backup_levels(levels_path)?;
+        write_atomic(levels_path, &contents)?;
+        repo.path = Some(levels_path.to_owned());
+        Ok(repo)
     }
     pub fn print_levels(&self) {
         for k in self.data.keys(){
@@ -59,10 +321,125 @@ impl LevelRepository {
     }
 }
 
+// write next to it and rename over, so a crash halfway leaves the old file intact
+pub fn write_atomic(path: &str, contents: &str) -> io::Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    let mut f = File::create(&tmp_path)?;
+    f.write_all(contents.as_bytes())?;
+    f.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+// copy the current levels file into the backups dir and throw out the oldest ones
+fn backup_levels(path: &str) -> io::Result<()> {
+    if fs::metadata(path).is_err() {
+        return Ok(());
+    }
+    fs::create_dir_all(backups_path)?;
+    let stamp = chrono::offset::Local::now().format("%Y%m%d-%H%M%S-%3f");
+    fs::copy(path, format!("{}/levels-{}.dat", backups_path, stamp))?;
+    for old in list_backups()?.iter().skip(backups_kept) {
+        fs::remove_file(format!("{}/{}.dat", backups_path, old))?;
+    }
+    Ok(())
+}
+
+// newest first, without the .dat
+pub fn list_backups() -> io::Result<Vec<String>> {
+    let mut backups = Vec::new();
+    if fs::metadata(backups_path).is_err() {
+        return Ok(backups);
+    }
+    for entry in fs::read_dir(backups_path)? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        if let Some(stem) = name.strip_suffix(".dat") {
+            if stem.starts_with("levels-") {
+                backups.push(stem.to_owned());
+            }
+        }
+    }
+    backups.sort();
+    backups.reverse();
+    Ok(backups)
+}
+
 // needs load and save to file
 #[derive(Serialize, Deserialize)]
 pub struct LevelMetadata {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub par: Option<i32>, // moves for a very good job
+    #[serde(default)]
+    pub solution: Option<String>, // shortest one anyone has done, as UDLR
     creator: String,
     date: String,
     level: Level,
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use crate::profile::*;
+
+    fn repo_with(name: &str) -> LevelRepository {
+        let mut repo = LevelRepository::in_memory();
+        let level = level(&format!("{}\n#####\n#pbt#\n#####", name));
+        repo.save_level(name.to_owned(), "santa".to_owned(), level).unwrap();
+        repo
+    }
+
+    #[test]
+    fn old_levels_get_the_same_id_every_load() {
+        let ids: Vec<String> = (0..2).map(|_| {
+            let mut repo = repo_with("first level");
+            repo.data.get_mut("first level").unwrap().id = String::new();
+            repo.assign_missing_ids();
+            repo.id_of(&"first level".to_owned()).unwrap()
+        }).collect();
+        assert_eq!(ids[0], ids[1]);
+        // what the shipped levels.dat was given, changing the hash would orphan progress
+        assert_eq!(ids[0], "fb4d0e79d7c15903-0000");
+    }
+
+    #[test]
+    fn restore_only_takes_listed_backups() {
+        match LevelRepository::restore_backup("../levels") {
+            Err(LevelRepositoryError::NoBackup(name)) => assert_eq!(name, "../levels"),
+            _ => panic!("restored from outside the backups dir"),
+        }
+    }
+
+    #[test]
+    fn rename_keeps_progress() {
+        let mut repo = repo_with("a");
+        let mut profile = Profile::default();
+        profile.complete(repo.progress_key(&"a".to_owned()), 1, 1);
+        let tokens = profile.tokens();
+        repo.rename_level(&"a".to_owned(), "b".to_owned()).unwrap();
+        assert_eq!(profile.tokens(), tokens);
+        assert!(profile.completed_levels.contains(&repo.progress_key(&"b".to_owned())));
+    }
+
+    #[test]
+    fn profiles_by_title_get_ids() {
+        let repo = repo_with("a");
+        let mut profile = Profile::default();
+        profile.complete("a".to_owned(), 3, 1);
+        profile.complete("manifest only".to_owned(), 3, 1);
+        profile.last_level = Some("a".to_owned());
+        profile.rekey(&|title| repo.id_of(title));
+        let id = repo.id_of(&"a".to_owned()).unwrap();
+        assert!(profile.completed_levels.contains(&id) && profile.completed_levels.contains("manifest only"));
+        assert_eq!((profile.best_moves[&id], profile.last_level), (3, Some(id)));
+    }
+
+    #[test]
+    fn locked_changes_nothing() {
+        let mut repo = repo_with("a");
+        repo.locked = true;
+        assert!(matches!(repo.rename_level(&"a".to_owned(), "b".to_owned()), Err(LevelRepositoryError::Locked)));
+        assert!(matches!(repo.delete_level(&"a".to_owned()), Err(LevelRepositoryError::Locked)));
+        assert!(repo.contains_level(&"a".to_owned()) && !repo.contains_level(&"b".to_owned()));
+    }
+}
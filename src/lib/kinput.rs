@@ -1,16 +1,21 @@
 use crate::lib::kmath::*;
 
 use std::collections::HashSet;
+use std::collections::HashMap;
 use std::time::{SystemTime, Instant, Duration};
 
 use glutin::event::VirtualKeyCode;
+use serde::{Serialize, Deserialize};
 
 use glutin::event::ElementState;
 use glutin::event::MouseButton;
 use glutin::event::Event;
 use glutin::event::WindowEvent::KeyboardInput;
+use glutin::event::WindowEvent::ReceivedCharacter;
 use glutin::event::WindowEvent::MouseInput;
 use glutin::event::WindowEvent::CursorMoved;
+use glutin::event::WindowEvent::MouseWheel;
+use glutin::event::MouseScrollDelta;
 use glutin::event::WindowEvent::Resized;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -29,19 +34,60 @@ pub struct FrameInputState {
     pub keys_pressed_this_frame: Vec<VirtualKeyCode>,
     pub keys_released_this_frame: Vec<VirtualKeyCode>,
     pub keys_held: HashSet<VirtualKeyCode>,
+    pub keys_held_since: HashMap<VirtualKeyCode, f64>, // t when it went down
+    pub text: String, // typed this frame, with shift and layout applied, no control characters
     pub lmb: KeyStatus,
     pub rmb: KeyStatus,
     pub mmb: KeyStatus,
+    pub scroll: f32, // wheel this frame in lines, up is positive
     pub t: f64,
     pub dt: f64,
     pub frame: u32,
     pub seed: u32,
 }
 
+// hold a key and it goes again after delay, then every interval, in seconds
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct KeyRepeat {
+    pub delay: f64,
+    pub interval: f64,
+}
+
+impl Default for KeyRepeat {
+    fn default() -> KeyRepeat {
+        KeyRepeat { delay: 0.25, interval: 0.12 }
+    }
+}
+
 impl FrameInputState {
+    #[cfg(test)]
+    pub fn blank() -> FrameInputState {
+        EventAggregator::new(1.0, 1.0).current
+    }
+
     pub fn just_pressed(&self, keycode: VirtualKeyCode) -> bool {
         self.keys_pressed_this_frame.contains(&keycode)
     }
+
+    // held long enough that it goes again this frame
+    pub fn repeated(&self, keycode: VirtualKeyCode, repeat: &KeyRepeat) -> bool {
+        let since = match self.keys_held_since.get(&keycode) {
+            Some(since) => *since,
+            None => return false,
+        };
+        // how many repeats there have been by a given time held
+        let count = |held: f64| if held < repeat.delay { -1 } else { ((held - repeat.delay) / repeat.interval.max(0.01)) as i64 };
+        let held = self.t - since;
+        count(held) != count(held - self.dt)
+    }
+
+    pub fn pressed_or_repeated(&self, keycode: VirtualKeyCode, repeat: &KeyRepeat) -> bool {
+        self.just_pressed(keycode) || self.repeated(keycode, repeat)
+    }
+
+    pub fn ctrl(&self) -> bool {
+        self.keys_held.contains(&VirtualKeyCode::LControl) || self.keys_held.contains(&VirtualKeyCode::RControl)
+    }
 }
 
 // Its basically just a state machine to go from events to polling behaviour
@@ -67,9 +113,12 @@ impl EventAggregator {
                 keys_pressed_this_frame: Vec::new(),
                 keys_released_this_frame: Vec::new(),
                 keys_held: HashSet::new(),
+                keys_held_since: HashMap::new(),
+                text: String::new(),
                 lmb: KeyStatus::Released, 
                 rmb: KeyStatus::Released, 
                 mmb: KeyStatus::Released, 
+                scroll: 0.0,
                 t: 0.0,
                 dt: 0.0,
                 frame: 0,
@@ -87,15 +136,21 @@ impl EventAggregator {
                         state, 
                     ..},
                 ..} => {
-                    if *state == ElementState::Pressed {
+                    // the os sends its own repeats as more presses, repeated() does the timing for held keys so they arent new presses
+                    if *state == ElementState::Pressed && !self.current.keys_held.contains(virtual_code) {
                         self.current.keys_pressed_this_frame.push(*virtual_code);
                         self.current.keys_held.insert(*virtual_code);
-                    } else {
+                        self.current.keys_held_since.insert(*virtual_code, self.current.t);
+                    } else if *state == ElementState::Released {
                         self.current.keys_released_this_frame.push(*virtual_code);
                         self.current.keys_held.remove(virtual_code);
+                        self.current.keys_held_since.remove(virtual_code);
                     }
                 },
 
+                // the os does shift, layouts and its own key repeat for text, backspace etc come through as control characters
+                ReceivedCharacter(c) if !c.is_control() => self.current.text.push(*c),
+
 
                 MouseInput { button: glutin::event::MouseButton::Left, state, ..} => {
                     if *state == ElementState::Pressed {
@@ -119,6 +174,13 @@ impl EventAggregator {
                     }
                 },
 
+                MouseWheel { delta, .. } => {
+                    self.current.scroll += match delta {
+                        MouseScrollDelta::LineDelta(_, y) => *y,
+                        MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / 20.0, // trackpads, about a line of text
+                    };
+                },
+
 
                 // Mouse motion
                 CursorMoved {
@@ -152,6 +214,8 @@ impl EventAggregator {
                 let state = self.current.clone();
                 self.current.keys_pressed_this_frame = Vec::new();
                 self.current.keys_released_this_frame = Vec::new();
+                self.current.text = String::new();
+                self.current.scroll = 0.0;
                 self.current.seed = khash(self.current.seed * 196513497);
                 self.current.lmb = match self.current.lmb {KeyStatus::JustPressed | KeyStatus::Pressed => KeyStatus::Pressed, KeyStatus::JustReleased | KeyStatus::Released => KeyStatus::Released};
                 self.current.mmb = match self.current.mmb {KeyStatus::JustPressed | KeyStatus::Pressed => KeyStatus::Pressed, KeyStatus::JustReleased | KeyStatus::Released => KeyStatus::Released};
@@ -164,4 +228,44 @@ impl EventAggregator {
 
         None
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(deprecated)]
+    fn key(code: VirtualKeyCode, state: ElementState) -> Event<'static, ()> {
+        Event::WindowEvent {
+            window_id: unsafe { glutin::window::WindowId::dummy() },
+            event: KeyboardInput {
+                device_id: unsafe { glutin::event::DeviceId::dummy() },
+                input: glutin::event::KeyboardInput { scancode: 0, state, virtual_keycode: Some(code), modifiers: Default::default() },
+                is_synthetic: false,
+            },
+        }
+    }
+
+    #[test]
+    fn os_repeats_arent_presses() {
+        let mut events = EventAggregator::new(100.0, 100.0);
+        events.handle_event(&key(VirtualKeyCode::D, ElementState::Pressed));
+        events.handle_event(&key(VirtualKeyCode::D, ElementState::Pressed));
+        assert_eq!(events.current.keys_pressed_this_frame, vec![VirtualKeyCode::D]);
+        events.handle_event(&key(VirtualKeyCode::D, ElementState::Released));
+        assert!(events.current.keys_held.is_empty());
+    }
+
+    #[test]
+    fn held_key_repeats_after_delay() {
+        let mut state = EventAggregator::new(100.0, 100.0).current;
+        let repeat = KeyRepeat { delay: 0.25, interval: 0.1 };
+        state.keys_held_since.insert(VirtualKeyCode::D, 0.0);
+        state.dt = 0.05;
+        let fired: Vec<bool> = (1..=10).map(|frame| {
+            state.t = frame as f64 * 0.05;
+            state.repeated(VirtualKeyCode::D, &repeat)
+        }).collect();
+        // goes at 0.25, 0.35, 0.45
+        assert_eq!(fired, vec![false, false, false, false, true, false, true, false, true, false]);
+    }
+}
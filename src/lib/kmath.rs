@@ -36,9 +36,9 @@ pub fn smoothstep(t: f32) -> f32 {
  ***************************************************/
 
 pub fn khash(mut state: u32) -> u32 {
-    state = (state ^ 2747636419) * 2654435769;
-    state = (state ^ (state >> 16)) * 2654435769;
-    state = (state ^ (state >> 16)) * 2654435769;
+    state = (state ^ 2747636419).wrapping_mul(2654435769);
+    state = (state ^ (state >> 16)).wrapping_mul(2654435769);
+    state = (state ^ (state >> 16)).wrapping_mul(2654435769);
     state
 }
 
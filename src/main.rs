@@ -9,6 +9,16 @@ mod session;
 mod snow;
 mod level_repository;
 mod terminal;
+mod commands;
+mod solver;
+mod profile;
+mod xsb;
+mod replay;
+mod rules;
+mod tween;
+mod audio;
+#[cfg(test)]
+mod test_util;
 
 use application::*;
 use glutin::event::{Event, WindowEvent};
@@ -21,6 +31,18 @@ use std::env;
 fn main() {
     env::set_var("RUST_BACKTRACE", "1");
 
+    // --run file: do the commands in it and quit, for generating levels and reproducing editor bugs
+    let args: Vec<String> = env::args().collect();
+    if let Some(idx) = args.iter().position(|a| a == "--run") {
+        match args.get(idx + 1) {
+            Some(path) => std::process::exit(if commands::batch(path) { 0 } else { 1 }),
+            None => {
+                println!("--run needs a file of terminal commands");
+                std::process::exit(2);
+            },
+        }
+    }
+
     let event_loop = glutin::event_loop::EventLoop::new();
     let mut application = Application::new(&event_loop);
     
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use serde::{Serialize, Deserialize};
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+
+use crate::audio::Volume;
+use crate::lib::kinput::KeyRepeat;
+use crate::level_repository::write_atomic;
+
+// player progress, kept apart from levels.dat so playing never touches the levels
+
+pub const profiles_path: &str = "./profiles.dat";
+pub const default_profile: &str = "santa";
+
+// levels are keyed by their repository id so renaming doesnt lose progress, levels only in the manifest go by title
+#[derive(Serialize, Deserialize, Default)]
+pub struct Profile {
+    pub completed_levels: HashSet<String>,
+    pub best_moves: HashMap<String, i32>,
+    #[serde(default)]
+    pub best_pushes: HashMap<String, i32>,
+    pub last_level: Option<String>,
+    #[serde(default)]
+    pub volume: Volume,
+    #[serde(default)]
+    pub key_repeat: KeyRepeat,
+}
+
+impl Profile {
+    // one token per level done
+    pub fn tokens(&self) -> i32 {
+        self.completed_levels.len() as i32
+    }
+
+    pub fn complete(&mut self, level: String, moves: i32, pushes: i32) {
+        let best = self.best_moves.entry(level.clone()).or_insert(moves);
+        *best = (*best).min(moves);
+        let best = self.best_pushes.entry(level.clone()).or_insert(pushes);
+        *best = (*best).min(pushes);
+        self.completed_levels.insert(level);
+    }
+
+    // profiles from before ids were keyed by title, key_of gives the id for titles that have one
+    pub fn rekey(&mut self, key_of: &dyn Fn(&String) -> Option<String>) {
+        let rekey_set = |set: &mut HashSet<String>| {
+            *set = set.drain().map(|k| key_of(&k).unwrap_or(k)).collect();
+        };
+        rekey_set(&mut self.completed_levels);
+        let rekey_map = |map: &mut HashMap<String, i32>| {
+            *map = map.drain().map(|(k, v)| (key_of(&k).unwrap_or(k), v)).collect();
+        };
+        rekey_map(&mut self.best_moves);
+        rekey_map(&mut self.best_pushes);
+        if let Some(last) = &self.last_level {
+            self.last_level = Some(key_of(last).unwrap_or(last.clone()));
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ProfileRepositoryError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+}
+
+impl fmt::Display for ProfileRepositoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProfileRepositoryError::Io(e) => write!(f, "io error: {}", e),
+            ProfileRepositoryError::Parse(e) => write!(f, "bad profiles file: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for ProfileRepositoryError {
+    fn from(e: io::Error) -> Self {
+        ProfileRepositoryError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ProfileRepositoryError {
+    fn from(e: serde_json::Error) -> Self {
+        ProfileRepositoryError::Parse(e)
+    }
+}
+
+impl ProfileRepositoryError {
+    pub fn is_missing_file(&self) -> bool {
+        match self {
+            ProfileRepositoryError::Io(e) => e.kind() == io::ErrorKind::NotFound,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProfileRepository {
+    pub current: String,
+    pub data: HashMap<String, Profile>,
+    #[serde(skip)]
+    locked: bool, // profiles file didnt load, so we wont clobber it
+    #[serde(skip)]
+    path: Option<String>, // where it saves to, none just keeps it in memory
+}
+
+impl ProfileRepository {
+    pub fn load(path: &str) -> Result<ProfileRepository, ProfileRepositoryError> {
+        let mut profiles_file = File::open(path)?;
+        let mut contents = String::new();
+        profiles_file.read_to_string(&mut contents)?;
+        let mut repo: ProfileRepository = serde_json::from_str(&contents)?;
+        repo.data.entry(repo.current.clone()).or_default();
+        repo.path = Some(path.to_owned());
+        Ok(repo)
+    }
+    fn empty(locked: bool, path: Option<String>) -> ProfileRepository {
+        let mut data = HashMap::new();
+        data.insert(default_profile.to_owned(), Profile::default());
+        ProfileRepository {
+            current: default_profile.to_owned(),
+            data,
+            locked,
+            path,
+        }
+    }
+    pub fn new() -> ProfileRepository {
+        ProfileRepository::empty(false, Some(profiles_path.to_owned()))
+    }
+    // for when the profiles file is there but broken, can play but progress isnt saved over it
+    pub fn new_locked() -> ProfileRepository {
+        // keeps the path so its the lock that stops it saving
+        ProfileRepository::empty(true, Some(profiles_path.to_owned()))
+    }
+    #[cfg(test)]
+    pub fn in_memory() -> ProfileRepository {
+        ProfileRepository::empty(false, None)
+    }
+    pub fn profile(&self) -> &Profile {
+        &self.data[&self.current]
+    }
+    pub fn profile_mut(&mut self) -> &mut Profile {
+        self.data.get_mut(&self.current).unwrap()
+    }
+    // returns true if its a new profile
+    pub fn select(&mut self, name: String) -> bool {
+        let new = !self.data.contains_key(&name);
+        self.data.entry(name.clone()).or_default();
+        self.current = name;
+        new
+    }
+    pub fn rekey(&mut self, key_of: &dyn Fn(&String) -> Option<String>) {
+        for profile in self.data.values_mut() {
+            profile.rekey(key_of);
+        }
+    }
+    pub fn save(&self) {
+        if self.locked {
+            println!("profiles file failed to load, not overwriting it");
+            return;
+        }
+        if let Some(path) = &self.path {
+            let str = serde_json::to_string(self).unwrap();
+            if write_atomic(path, &str).is_err() {
+                println!("couldn't write {}", path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn only_a_missing_file_starts_fresh() {
+        let path = &temp_path("profiles.dat");
+        assert!(ProfileRepository::load(path).err().unwrap().is_missing_file());
+        std::fs::write(path, "{\"current\": \"santa\", \"data\": {\"santa\": ").unwrap();
+        let err = ProfileRepository::load(path).err().unwrap();
+        assert!(!err.is_missing_file() && matches!(err, ProfileRepositoryError::Parse(_)));
+        // and the broken one stays as it was
+        ProfileRepository::empty(true, Some(path.to_owned())).save();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "{\"current\": \"santa\", \"data\": {\"santa\": ");
+        // the same thing unlocked does write
+        ProfileRepository::empty(false, Some(path.to_owned())).save();
+        assert!(ProfileRepository::load(path).is_ok());
+        std::fs::remove_file(path).unwrap();
+    }
+}
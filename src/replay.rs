@@ -0,0 +1,166 @@
+use crate::level::*;
+
+// move strings, one letter a move: U D L R
+// used for recorded solutions, solver output and replaying them against a fresh instance
+
+pub fn dir_char(dir: (i32, i32)) -> char {
+    match dir {
+        (0, -1) => 'U',
+        (0, 1) => 'D',
+        (-1, 0) => 'L',
+        (1, 0) => 'R',
+        _ => '?',
+    }
+}
+
+pub fn char_dir(c: char) -> Option<(i32, i32)> {
+    match c.to_ascii_uppercase() {
+        'U' => Some((0, -1)),
+        'D' => Some((0, 1)),
+        'L' => Some((-1, 0)),
+        'R' => Some((1, 0)),
+        _ => None,
+    }
+}
+
+pub fn encode_moves(moves: &[(i32, i32)]) -> String {
+    moves.iter().map(|dir| dir_char(*dir)).collect()
+}
+
+// whitespace is ignored so long solutions can be wrapped
+pub fn decode_moves(s: &str) -> Option<Vec<(i32, i32)>> {
+    s.chars().filter(|c| !c.is_whitespace()).map(char_dir).collect()
+}
+
+pub struct Replay {
+    pub level: LevelInstance,
+    moves: Vec<(i32, i32)>,
+    idx: usize,
+}
+
+impl Replay {
+    pub fn new(level: &Level, moves: &str) -> Option<Replay> {
+        Some(Replay {
+            level: level.instance(),
+            moves: decode_moves(moves)?,
+            idx: 0,
+        })
+    }
+
+    // None once its out of moves, otherwise whether the move did anything
+    pub fn step(&mut self) -> Option<bool> {
+        let dir = *self.moves.get(self.idx)?;
+        self.idx += 1;
+        Some(self.level.try_move(dir))
+    }
+
+    // plays out the rest, returns how many moves didnt go anywhere
+    pub fn run(&mut self) -> usize {
+        let mut blocked = 0;
+        while let Some(moved) = self.step() {
+            if !moved {
+                blocked += 1;
+            }
+        }
+        blocked
+    }
+}
+
+pub fn solves(level: &Level, moves: &str) -> bool {
+    match Replay::new(level, moves) {
+        Some(mut replay) => {
+            replay.run();
+            replay.level.victorious()
+        },
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use crate::manifest::*;
+    use crate::level_repository::*;
+
+    // a known solution for every level in the manifest, keyed by title
+    const manifest_solutions: &[(&str, &str)] = &[
+        ("first level", "ULURDRU"),
+        ("second level", "UULUURULDDDRUU"),
+        ("one step forward, 2 steps back", "DRUURRDRRULLL"),
+        ("sticky", "DLLULLDURRDLULDDLDRRRRLLLLDRRRR"),
+        ("sticky 2", "DLULURRRRLLLLUURRRRRRRDDDDLLUUUDDDRRUUUULLLLL"),
+        ("sticky 3", "URDDDDRDLULDDRDRUUUUU"),
+        ("ice to meet you", "DRUL"),
+        ("maze 1", "DRUDLURLDRU"),
+        ("Patience", "UULUURLDDDDRRURLDLURULL"),
+        ("block puzzle", "UUURRRRDLDDRRUDLLUURRRLLURRR"),
+        ("cross", "RRRUUDDLLLLLUUURLDDDRRUDRRURUUU"),
+        ("diode", "RRRRRRUUUUUULLLLLRRRRRDDDDDDLLLLURUULL"),
+        ("I", "DLDDRUUUDLLLLULUURRRR"),
+        ("one way street", "RULURRDDRURULRRDLDLURDLLULDLDRRDRURULLLULDDUULDD"),
+        ("crate to meet you", "RRURULDLU"),
+        ("assist", "DRRDLDLUULLURRDRU"),
+        ("Overshoot", "RRRURULLLDDLUDLLUUURRRRRRDDDRU"),
+        ("tight", "URRDULLDRDLULDRU"),
+        ("iceee", "DLURDDL"),
+        ("corner", "UUUURURRRURDDUULLLLLLULDDURRRDDLDDDDRRRRRRUUL"),
+        ("sequence 2", "UURRRLDDRUUUDDDRUUDRUULLDLUDLLUUR"),
+        ("2 roads", "ULULLUUUUURRRRRRDRULDDDDDRRRRRDRRUUUUUDDDDLLLLLLDLUUUUUUR"),
+        ("deja vu", "DDLLLLLLURRRRRDRUDRRRRRRULLLDLLLUDLLURDRUDRRRULDLLUDRRULDLUULUULDLDRRRRURDD"),
+    ];
+
+    // levels.dat levels with presents to deliver that nobody can solve yet, and why
+    const unsolved: &[(&str, &str)] = &[
+        ("samslevel", "unfinished, the top present is walled in"),
+        ("first", "hub, the present by the receptacle is boxed in"),
+    ];
+
+    #[test]
+    fn manifest_levels_replay() {
+        for s in noice_levels.iter().chain(ice_levels).chain(crate_levels) {
+            let level = level(s);
+            let (_, moves) = manifest_solutions.iter().find(|(title, _)| *title == level.title)
+                .unwrap_or_else(|| panic!("no solution for {}", level.title));
+            assert!(solves(&level, moves), "{} isnt solved by {}", level.title, moves);
+        }
+    }
+
+    // every level with receptacles has a solution that works, unless its on the list
+    #[test]
+    fn stored_solutions_replay() {
+        let repo = LevelRepository::load(levels_path).expect("couldnt load levels.dat");
+        for (name, meta) in repo.data.iter() {
+            let level = repo.get_level_by_id(&meta.id).unwrap();
+            let listed = unsolved.iter().any(|(title, _)| title == name);
+            match &meta.solution {
+                Some(moves) => {
+                    assert!(solves(&level, moves), "{} isnt solved by its stored solution", name);
+                    assert!(!listed, "{} has a solution now, take it off the unsolved list", name);
+                },
+                None => assert!(listed || !level.entities.iter().any(|(e, _, _)| *e == Entity::Receptacle), "{} has receptacles but no solution", name),
+            }
+        }
+        for (title, _) in unsolved {
+            assert!(repo.contains_level(&title.to_string()), "{} is on the unsolved list but not in levels.dat", title);
+        }
+    }
+
+    #[test]
+    fn moves_round_trip() {
+        let moves = vec![(0, -1), (0, 1), (-1, 0), (1, 0), (1, 0)];
+        assert_eq!(encode_moves(&moves), "UDLRR");
+        assert_eq!(decode_moves("UDLRR"), Some(moves.clone()));
+        assert_eq!(decode_moves("ud\nl rr"), Some(moves));
+        assert_eq!(decode_moves("UDX"), None);
+    }
+
+    #[test]
+    fn partial_solution_isnt_victorious() {
+        let level = level(noice_levels[0]);
+        assert!(!solves(&level, "ULURDR"));
+        let mut replay = Replay::new(&level, "DULURDRU").unwrap();
+        assert_eq!(replay.run(), 1);
+        assert!(replay.level.victorious());
+    }
+}
@@ -0,0 +1,375 @@
+use crate::level::*;
+
+// the push and slide rules, as a pure function of (entities, direction) -> (entities, events)
+// nothing here depends on what order things are in current_entities
+//
+// a move happens in ticks. tick 0 is the push:
+//   every player pushes at once. a push is the player plus every movable in the cells in front of it,
+//   up to the first cell with no movables. it goes if that cell isnt a wall and nothing there blocks
+//   the front of the chain (player_allowed if the front is all players, boxes_allowed otherwise)
+//   if a pow in the chain is about to run into a wall or a pow target it goes off instead and nobody moves
+// then slides, one cell a tick, until nothing is sliding:
+//   anything that moved onto ice keeps going the same way. everything moves the same way within a move,
+//   so sliders go front first and anything behind sees where the front ones ended up
+//   a slider pushes whatever is in front of it like a player would, and it all moves at most once a tick
+//   a slider that cant go stops. if it was a box stopped by a tree, the tree comes down
+// stacked movables are one group, they move or stop together
+
+// everything that happened in a move, in order, for animation and sound
+// every cell anything moves is exactly one of Stepped, Pushed or Slid
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    Stepped { from: (i32, i32), to: (i32, i32), tick: usize }, // a player walked
+    Pushed { entity: Entity, from: (i32, i32), to: (i32, i32), tick: usize }, // shoved by whatever was behind it
+    Slid { entity: Entity, from: (i32, i32), to: (i32, i32), tick: usize }, // moved under its own momentum
+    StartedSliding { entity: Entity, at: (i32, i32), tick: usize },
+    Stopped { entity: Entity, at: (i32, i32), tick: usize }, // was sliding and ran into something
+    Delivered { at: (i32, i32), tick: usize }, // present landed on a receptacle
+    EnteredPortal { at: (i32, i32), tick: usize },
+    Detonated { at: (i32, i32), hit: (i32, i32), tick: usize },
+    Felled { at: (i32, i32), tick: usize },
+    // from LevelInstance rather than step, followed by the moves being undone (backwards) or redone
+    Undo,
+    Redo,
+    Restart,
+}
+
+pub type Entities = Vec<(Entity, i32, i32)>;
+pub type Movement<'a> = (&'a Entity, (i32, i32), (i32, i32), usize); // what, from, to, tick
+
+impl Event {
+    pub fn movement(&self) -> Option<Movement<'_>> {
+        match self {
+            Event::Stepped { from, to, tick } => Some((&Entity::Player, *from, *to, *tick)),
+            Event::Pushed { entity, from, to, tick } | Event::Slid { entity, from, to, tick } => Some((entity, *from, *to, *tick)),
+            _ => None,
+        }
+    }
+
+    // the same thing played backwards, for undo
+    pub fn reversed(&self) -> Option<Event> {
+        match self.clone() {
+            Event::Stepped { from, to, tick } => Some(Event::Stepped { from: to, to: from, tick }),
+            Event::Pushed { entity, from, to, tick } => Some(Event::Pushed { entity, from: to, to: from, tick }),
+            Event::Slid { entity, from, to, tick } => Some(Event::Slid { entity, from: to, to: from, tick }),
+            _ => None,
+        }
+    }
+}
+
+enum Push {
+    Moves(Vec<usize>),
+    Pow((i32, i32), (i32, i32)),
+    Tree((i32, i32)),
+    Blocked,
+}
+
+struct World<'a> {
+    level: &'a Level,
+    entities: Vec<(Entity, i32, i32)>,
+    gone: Vec<bool>, // blown up this move, dropped at the end so indices stay put
+    events: Vec<Event>,
+}
+
+impl<'a> World<'a> {
+    fn is_wall(&self, pos: (i32, i32)) -> bool {
+        pos.0 < 0 || pos.1 < 0 || pos.0 >= self.level.w || pos.1 >= self.level.h ||
+            self.level.tiles[(self.level.w * pos.1 + pos.0) as usize] == Tile::Wall
+    }
+
+    fn is_ice(&self, pos: (i32, i32)) -> bool {
+        !self.is_wall(pos) && self.level.tiles[(self.level.w * pos.1 + pos.0) as usize] == Tile::Ice
+    }
+
+    fn at(&self, pos: (i32, i32)) -> impl Iterator<Item = usize> + '_ {
+        (0..self.entities.len()).filter(move |idx| !self.gone[*idx] && self.entities[*idx].1 == pos.0 && self.entities[*idx].2 == pos.1)
+    }
+
+    fn movers_at(&self, pos: (i32, i32)) -> Vec<usize> {
+        self.at(pos).filter(|idx| self.entities[*idx].0.can_move()).collect()
+    }
+
+    fn any_at(&self, pos: (i32, i32), f: impl Fn(&Entity) -> bool) -> bool {
+        self.at(pos).any(|idx| f(&self.entities[idx].0))
+    }
+
+    // the chain starting at from, and whether it goes
+    fn push(&self, from: (i32, i32), dir: (i32, i32), moved: &[bool], sliding: bool) -> Push {
+        let mut chain = Vec::new();
+        let mut pos = from;
+        loop {
+            let here = self.movers_at(pos);
+            if here.iter().any(|idx| moved[*idx]) {
+                return Push::Blocked;
+            }
+            let next = (pos.0 + dir.0, pos.1 + dir.1);
+            if self.any_at(pos, |e| *e == Entity::Pow) && (self.is_wall(next) || self.any_at(next, |e| e.pow_target())) {
+                return Push::Pow(pos, next);
+            }
+            let players_only = here.iter().all(|idx| self.entities[*idx].0 == Entity::Player);
+            chain.extend(here);
+            if self.is_wall(next) {
+                return Push::Blocked;
+            }
+            if self.movers_at(next).is_empty() {
+                if sliding && !players_only && self.any_at(next, |e| *e == Entity::Tree) {
+                    return Push::Tree(next);
+                }
+                let blocked = self.any_at(next, |e| if players_only { !e.player_allowed() } else { !e.boxes_allowed() });
+                return if blocked { Push::Blocked } else { Push::Moves(chain) };
+            }
+            pos = next;
+        }
+    }
+
+    // moves the chain from from, returns the ones that landed on ice
+    fn apply(&mut self, from: (i32, i32), chain: &[usize], dir: (i32, i32), sliding: &[usize], moved: &mut [bool], tick: usize) -> Vec<usize> {
+        let mut landed_on_ice = Vec::new();
+        for idx in chain.iter().copied() {
+            if moved[idx] {
+                continue;
+            }
+            moved[idx] = true;
+            let (e, i, j) = self.entities[idx].clone();
+            let to = (i + dir.0, j + dir.1);
+            self.entities[idx] = (e.clone(), to.0, to.1);
+            self.events.push(match (&e, (i, j) == from, sliding.contains(&idx)) {
+                (_, true, true) => Event::Slid { entity: e.clone(), from: (i, j), to, tick },
+                (Entity::Player, true, false) => Event::Stepped { from: (i, j), to, tick },
+                _ => Event::Pushed { entity: e.clone(), from: (i, j), to, tick },
+            });
+            if e == Entity::Present && self.any_at(to, |x| *x == Entity::Receptacle) {
+                self.events.push(Event::Delivered { at: to, tick });
+            }
+            if e == Entity::Player && self.any_at(to, |x| matches!(x, Entity::Portal(_, _) | Entity::ReturnPortal)) {
+                self.events.push(Event::EnteredPortal { at: to, tick });
+            }
+            if self.is_ice(to) {
+                if !sliding.contains(&idx) {
+                    self.events.push(Event::StartedSliding { entity: e, at: to, tick });
+                }
+                landed_on_ice.push(idx);
+            }
+        }
+        landed_on_ice
+    }
+
+    fn stopped(&mut self, at: (i32, i32), sliding: &[usize], tick: usize) {
+        for idx in sliding.iter().copied() {
+            let (e, i, j) = &self.entities[idx];
+            if !self.gone[idx] && (*i, *j) == at {
+                self.events.push(Event::Stopped { entity: e.clone(), at, tick });
+            }
+        }
+    }
+
+    // pow is gone, and so is whatever it hit (walls are fine)
+    fn detonate(&mut self, pos: (i32, i32), hit: (i32, i32), tick: usize) {
+        for idx in 0..self.entities.len() {
+            let (e, i, j) = &self.entities[idx];
+            if (*e == Entity::Pow && (*i, *j) == pos) || (e.pow_target() && (*i, *j) == hit) {
+                self.gone[idx] = true;
+            }
+        }
+        self.events.push(Event::Detonated { at: pos, hit, tick });
+    }
+
+    fn fell(&mut self, pos: (i32, i32), tick: usize) {
+        for idx in 0..self.entities.len() {
+            if !self.gone[idx] && self.entities[idx].0 == Entity::Tree && (self.entities[idx].1, self.entities[idx].2) == pos {
+                self.entities[idx].0 = Entity::TreeStump;
+            }
+        }
+        self.events.push(Event::Felled { at: pos, tick });
+    }
+
+    // cells with something in them, front first
+    fn cells_front_first(&self, idxs: &[usize], dir: (i32, i32)) -> Vec<(i32, i32)> {
+        let mut cells: Vec<(i32, i32)> = idxs.iter().filter(|idx| !self.gone[**idx]).map(|idx| (self.entities[*idx].1, self.entities[*idx].2)).collect();
+        cells.sort_by_key(|(i, j)| (-(i * dir.0 + j * dir.1), *i, *j));
+        cells.dedup();
+        cells
+    }
+
+    fn resolve(&mut self, from: (i32, i32), push: Push, dir: (i32, i32), sliding: &[usize], moved: &mut [bool], tick: usize) -> Vec<usize> {
+        match push {
+            Push::Moves(chain) => return self.apply(from, &chain, dir, sliding, moved, tick),
+            Push::Pow(pos, hit) => {
+                self.stopped(from, sliding, tick);
+                // two players behind the same pow only set it off once
+                if !self.events.contains(&Event::Detonated { at: pos, hit, tick }) {
+                    self.detonate(pos, hit, tick);
+                }
+            },
+            Push::Tree(at) => {
+                self.stopped(from, sliding, tick);
+                self.fell(at, tick);
+            },
+            Push::Blocked => self.stopped(from, sliding, tick),
+        }
+        Vec::new()
+    }
+}
+
+// None if nothing happens
+pub fn step(level: &Level, entities: &[(Entity, i32, i32)], dir: (i32, i32)) -> Option<(Entities, Vec<Event>)> {
+    let mut w = World {
+        level,
+        entities: entities.to_vec(),
+        gone: vec![false; entities.len()],
+        events: Vec::new(),
+    };
+
+    // work out every push before doing any of them, so players dont see each other half moved
+    let players: Vec<usize> = (0..entities.len()).filter(|idx| entities[*idx].0 == Entity::Player).collect();
+    let mut moved = vec![false; entities.len()];
+    let pushes: Vec<((i32, i32), Push)> = w.cells_front_first(&players, dir).into_iter().map(|pos| (pos, w.push(pos, dir, &moved, false))).collect();
+    let mut sliding = Vec::new();
+    for (pos, push) in pushes {
+        sliding.extend(w.resolve(pos, push, dir, &[], &mut moved, 0));
+    }
+
+    let mut tick = 0;
+    while !sliding.is_empty() {
+        tick += 1;
+        let mut moved = vec![false; w.entities.len()];
+        let mut next = Vec::new();
+        for pos in w.cells_front_first(&sliding, dir) {
+            let push = w.push(pos, dir, &moved, true);
+            next.extend(w.resolve(pos, push, dir, &sliding, &mut moved, tick));
+        }
+        sliding = next;
+    }
+
+    if w.events.is_empty() {
+        return None;
+    }
+    let gone = w.gone;
+    let entities = w.entities.into_iter().enumerate().filter(|(idx, _)| !gone[*idx]).map(|(_, e)| e).collect();
+    Some((entities, w.events))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    fn sorted(mut entities: Vec<(Entity, i32, i32)>) -> Vec<(Entity, i32, i32)> {
+        entities.sort_by_key(|(e, i, j)| (format!("{:?}", e), *i, *j));
+        entities
+    }
+
+    fn positions(entities: &[(Entity, i32, i32)], e: Entity) -> Vec<(i32, i32)> {
+        let mut ps: Vec<(i32, i32)> = entities.iter().filter(|(x, _, _)| *x == e).map(|(_, i, j)| (*i, *j)).collect();
+        ps.sort();
+        ps
+    }
+
+    // same result whichever way round the entities are listed
+    fn check_order_independent(level: &Level, dir: (i32, i32)) -> Option<Vec<(Entity, i32, i32)>> {
+        let forward = step(level, &level.entities, dir).map(|(e, _)| sorted(e));
+        let mut reversed = level.entities.clone();
+        reversed.reverse();
+        let backward = step(level, &reversed, dir).map(|(e, _)| sorted(e));
+        assert_eq!(forward, backward);
+        forward
+    }
+
+    #[test]
+    fn chain_of_players_moves_once() {
+        let level = level("train\n#######\n#ppb  #\n#######");
+        let after = check_order_independent(&level, (1, 0)).unwrap();
+        assert_eq!(positions(&after, Entity::Player), vec![(2, 1), (3, 1)]);
+        assert_eq!(positions(&after, Entity::Present), vec![(4, 1)]);
+    }
+
+    #[test]
+    fn blocked_chain_doesnt_move() {
+        let level = level("blocked\n#####\n#pbc#\n#####");
+        assert_eq!(check_order_independent(&level, (1, 0)), None);
+    }
+
+    #[test]
+    fn slide_carries_chain_to_wall() {
+        let level = level("slide\n#########\n#pB/////#\n#########");
+        let after = check_order_independent(&level, (1, 0)).unwrap();
+        assert_eq!(positions(&after, Entity::Present), vec![(7, 1)]);
+        // the player stepped onto ice too, so it comes along behind
+        assert_eq!(positions(&after, Entity::Player), vec![(6, 1)]);
+    }
+
+    #[test]
+    fn sliders_in_a_row_stack_up() {
+        let level = level("pileup\n##########\n#pBB////c#\n##########");
+        let after = check_order_independent(&level, (1, 0)).unwrap();
+        assert_eq!(positions(&after, Entity::Present), vec![(6, 1), (7, 1)]);
+    }
+
+    #[test]
+    fn slider_pushes_resting_box() {
+        let level = level("knock\n#########\n#pB//b  #\n#########");
+        let after = check_order_independent(&level, (1, 0)).unwrap();
+        // the slider shoves the resting one along and both stop on the snow, then the sliding player shoves them again
+        assert_eq!(positions(&after, Entity::Present), vec![(6, 1), (7, 1)]);
+        assert_eq!(positions(&after, Entity::Player), vec![(5, 1)]);
+    }
+
+    #[test]
+    fn slide_events_go_cell_by_cell() {
+        let level = level("ticks\n#######\n#pB// #\n#######");
+        let (_, events) = step(&level, &level.entities, (1, 0)).unwrap();
+        let present_moves: Vec<((i32, i32), usize)> = events.iter().filter_map(|ev| match ev.movement() {
+            Some((Entity::Present, _, to, tick)) => Some((to, tick)),
+            _ => None,
+        }).collect();
+        assert_eq!(present_moves, vec![((3, 1), 0), ((4, 1), 1), ((5, 1), 2)]);
+    }
+
+    #[test]
+    fn slide_events_in_order() {
+        let level = level("events\n#######\n#pB/T #\n#######");
+        let (_, events) = step(&level, &level.entities, (1, 0)).unwrap();
+        assert_eq!(events, vec![
+            Event::Stepped { from: (1, 1), to: (2, 1), tick: 0 },
+            Event::StartedSliding { entity: Entity::Player, at: (2, 1), tick: 0 },
+            Event::Pushed { entity: Entity::Present, from: (2, 1), to: (3, 1), tick: 0 },
+            Event::StartedSliding { entity: Entity::Present, at: (3, 1), tick: 0 },
+            Event::Slid { entity: Entity::Present, from: (3, 1), to: (4, 1), tick: 1 },
+            Event::Delivered { at: (4, 1), tick: 1 },
+            Event::Slid { entity: Entity::Player, from: (2, 1), to: (3, 1), tick: 1 },
+            Event::Slid { entity: Entity::Present, from: (4, 1), to: (5, 1), tick: 2 },
+            // the present ran off onto snow, the player bumps into it
+            Event::Slid { entity: Entity::Player, from: (3, 1), to: (4, 1), tick: 2 },
+            Event::Stopped { entity: Entity::Player, at: (4, 1), tick: 3 },
+        ]);
+    }
+
+    #[test]
+    fn edge_of_map_is_wall() {
+        let level = level("edge\npbt");
+        assert!(step(&level, &level.entities, (-1, 0)).is_none());
+        assert!(step(&level, &level.entities, (0, -1)).is_none());
+        let (after, _) = step(&level, &level.entities, (1, 0)).unwrap();
+        assert_eq!(positions(&after, Entity::Present), vec![(2, 0)]);
+    }
+
+    #[test]
+    fn pow_detonates_on_crate() {
+        let level = level("pow\n#######\n#px c #\n#######");
+        let (after, _) = step(&level, &level.entities, (1, 0)).unwrap();
+        assert_eq!(positions(&after, Entity::Pow), vec![(3, 1)]);
+        let (after, events) = step(&level, &after, (1, 0)).unwrap();
+        assert!(positions(&after, Entity::Pow).is_empty());
+        assert!(positions(&after, Entity::Crate).is_empty());
+        assert!(events.iter().any(|ev| matches!(ev, Event::Detonated { .. })));
+    }
+
+    #[test]
+    fn sliding_box_fells_tree() {
+        let level = level("tree\n#######\n#pb//y#\n#######");
+        let (after, events) = step(&level, &level.entities, (1, 0)).unwrap();
+        assert!(positions(&after, Entity::Tree).is_empty());
+        assert_eq!(positions(&after, Entity::TreeStump).len(), 1);
+        assert!(events.iter().any(|ev| matches!(ev, Event::Felled { .. })));
+    }
+}
@@ -1,4 +1,3 @@
-use std::collections::HashSet;
 use std::collections::HashMap;
 
 use glutin::event::VirtualKeyCode;
@@ -7,32 +6,40 @@ use crate::level::*;
 use crate::instance::*;
 use crate::level_repository;
 use crate::level_repository::LevelRepository;
+use crate::level_repository::LevelRepositoryError;
 use crate::level_repository::levels_path;
 use crate::renderer::*;
 use crate::manifest::*;
 use crate::terminal::*;
+use crate::commands::*;
+use crate::profile::*;
+use crate::replay::*;
+use crate::audio::*;
 use crate::lib::kinput::*;
 use crate::lib::kmath::*;
 
 // a high level thing for organizing gameplay. contains progress and level repository
 pub struct Session {
-    name: String,
     date: String,
 
-    level_repository: LevelRepository,    
-    completed_levels: HashSet<String>,
+    pub level_repository: LevelRepository,    
+    pub profiles: ProfileRepository,
+    pub audio: Audio,
 
-    current_level: Level,
-    current_instance: Option<Instance>, // made off current level, if it exists we playing if not we editing
+    pub current_level: Level,
+    pub current_instance: Option<Instance>, // made off current level, if it exists we playing if not we editing
+    pub return_stack: Vec<(Instance, (i32, i32))>, // levels we went through a portal from, and which portal
     // i want a terminal: open level, browse levels, save, load, etc
 
     tile_selection: Option<usize>,
     entity_selection: Option<usize>,
 
-    terminal: Terminal,
+    pub terminal: Terminal,
 
-    place_tokens: i32,
-    place_link: String,
+    pub place_tokens: i32,
+    pub place_link: String,
+    pub pending_delete: Option<String>, // needs to be asked for twice
+    pub scripts: Vec<String>, // being run, so one cant run itself
 }
 
 // lol present is such a macguffin, I wouldn't mind if there was something that made sense to happen once they were on a specific square, and you got a specific outcome and that was progress
@@ -43,22 +50,122 @@ pub struct Session {
 
 impl Session {
     pub fn new() -> Session {
+        Session::load(true)
+    }
+
+    // for scripts from the command line and tests
+    pub fn new_quiet() -> Session {
+        Session::load(false)
+    }
+
+    fn load(sound: bool) -> Session {
+        let mut terminal = Terminal::new();
+        terminal.load_history(history_path);
+        let mut level_repository = match LevelRepository::load(levels_path) {
+            Ok(repo) => repo,
+            Err(e) if e.is_missing_file() => LevelRepository::new(),
+            Err(e) => {
+                println!("{}", e);
+                terminal.tprint(format!("couldnt load {}: {}", levels_path, e));
+                terminal.tprint("saving is disabled until its fixed".to_owned());
+                LevelRepository::new_locked()
+            },
+        };
+        for problem in level_repository.validate() {
+            terminal.tprint(problem);
+        }
+        let profiles = match ProfileRepository::load(profiles_path) {
+            Ok(profiles) => profiles,
+            Err(e) if e.is_missing_file() => ProfileRepository::new(),
+            Err(e) => {
+                println!("{}", e);
+                terminal.tprint(format!("couldnt load {}: {}", profiles_path, e));
+                terminal.tprint("progress wont be saved until its fixed".to_owned());
+                ProfileRepository::new_locked()
+            },
+        };
+        Session::from_parts(terminal, level_repository, profiles, sound)
+    }
+    // whatever they were loaded from, tests put together in memory ones so nothing touches the real files
+    pub fn from_parts(terminal: Terminal, level_repository: LevelRepository, mut profiles: ProfileRepository, sound: bool) -> Session {
+        profiles.rekey(&|title| level_repository.id_of(title));
+        let audio = if sound {
+            let mut audio = Audio::new(profiles.profile().volume);
+            audio.play_music();
+            audio
+        } else {
+            Audio::with_backend(Box::new(NullBackend::default()), profiles.profile().volume)
+        };
+        let current_level = profiles.profile().last_level.as_ref()
+            .and_then(|key| level_repository.get_linked_level(key))
+            .unwrap_or(Level::from_string(noice_levels[0]).unwrap());
         Session {
-            name: "santa".to_owned(),
             date: "genesis".to_owned(),
-            level_repository: LevelRepository::load(levels_path).unwrap_or(LevelRepository::new()),
-            completed_levels: HashSet::new(),
-            current_level: Level::from_string(noice_levels[0]).unwrap(),
+            level_repository,
+            profiles,
+            audio,
+            current_level,
             current_instance: None,
+            return_stack: Vec::new(),
 
             tile_selection: None,
             entity_selection: None,
 
-            terminal: Terminal::new(),
+            terminal,
 
             place_tokens: 0,
             place_link: "void".to_owned(),
+            pending_delete: None,
+            scripts: Vec::new(),
+        }
+    }
+
+    // back out to the level we came from, standing on the portal we used
+    fn pop_instance(&mut self) -> bool {
+        if let Some((mut origin, portal_pos)) = self.return_stack.pop() {
+            origin.portal_lock = Some(portal_pos);
+            self.current_instance = Some(origin);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn travel(&mut self, dest: &String, portal_pos: (i32, i32)) {
+        if let Some(dest_level) = self.level_repository.get_linked_level(dest) {
+            // levels link back to their hub with plain portals, going back somewhere thats on the stack unwinds to it
+            if let Some(idx) = self.return_stack.iter().position(|(instance, _)| instance.level.l.title == dest_level.title) {
+                self.return_stack.truncate(idx + 1);
+                self.pop_instance();
+            } else {
+                let origin = self.current_instance.replace(Instance::new(dest_level.instance(), self.level_repository.par_of(&dest_level.title))).unwrap();
+                self.return_stack.push((origin, portal_pos));
+            }
+            self.profiles.profile_mut().last_level = Some(self.level_repository.progress_key(&dest_level.title));
+            self.profiles.save();
+        } else {
+            self.terminal.tprint(format!("portal links to missing level {}", dest));
+            self.current_instance.as_mut().unwrap().portal_lock = Some(portal_pos);
+        }
+    }
+
+    pub fn save_current_level(&mut self) -> Result<(), String> {
+        let result = self.level_repository.save_level(self.current_level.title.clone(), self.profiles.current.clone(), self.current_level.clone());
+        self.report(result)?;
+        // saving points portal links at ids, keep editing that version
+        if let Some(saved) = self.level_repository.get_level(&self.current_level.title) {
+            self.current_level = saved;
+        }
+        Ok(())
+    }
+
+    // problems get printed, an error comes back for the command to fail with
+    pub fn report(&mut self, result: Result<Vec<String>, LevelRepositoryError>) -> Result<(), String> {
+        let problems = result.map_err(|e| format!("couldnt save: {}", e))?;
+        for problem in problems {
+            self.terminal.tprint(problem);
         }
+        Ok(())
     }
 
     pub fn frame(&mut self, inputs: &FrameInputState, rc: &mut Vec<RenderCommand>) {
@@ -66,26 +173,48 @@ impl Session {
         if let Some(ci) = &mut self.current_instance {
             // esc back to edit mode
 
-            let outcome = ci.frame(inputs, rc, self.completed_levels.len() as i32, inputs.t as f32);
-            if outcome != InstanceFrameOutcome::None {
-                println!("outcome: {:?}", outcome);
-            }
+            let outcome = ci.frame(inputs, rc, self.profiles.profile().tokens(), inputs.t as f32, &self.profiles.profile().key_repeat);
+            let (moves, pushes) = (ci.level.moves(), ci.level.pushes);
+            let path = encode_moves(&ci.level.path);
+            self.audio.events(&ci.events, &ci.level.l);
             match outcome {
-                InstanceFrameOutcome::Completion(name) => {self.completed_levels.insert(name);},
-                InstanceFrameOutcome::Bail => self.current_instance = None,
-                InstanceFrameOutcome::Travel(dest) => self.current_instance = Some(Instance::new(self.level_repository.get_level(&dest).unwrap().instance())),
+                InstanceFrameOutcome::Completion(name) => {
+                    self.audio.play("win.ogg");
+                    let key = self.level_repository.progress_key(&name);
+                    self.profiles.profile_mut().complete(key, moves, pushes);
+                    self.profiles.save();
+                    if self.level_repository.contains_level(&name) {
+                        match self.level_repository.record_solution(&name, path) {
+                            Ok(true) => self.terminal.tprint(format!("new best solution for {}: {} moves", name, moves)),
+                            Ok(false) => {},
+                            Err(e) => self.terminal.tprint(format!("couldnt save solution: {}", e)),
+                        }
+                    }
+                },
+                InstanceFrameOutcome::Bail => {
+                    if !self.pop_instance() {
+                        self.current_instance = None;
+                    }
+                },
+                InstanceFrameOutcome::Return => {
+                    let complete = self.current_instance.as_ref().map(|ci| ci.complete).unwrap_or(false);
+                    if !self.pop_instance() && complete {
+                        self.current_instance = None;
+                    }
+                },
+                InstanceFrameOutcome::Travel(dest, portal_pos) => self.travel(&dest, portal_pos),
                 InstanceFrameOutcome::None => {},
             }
         } else {
             let tiles = vec![Tile::Snow, Tile::Ice, Tile::Wall, Tile::Wall];
-            let entities = vec![Entity::Player, Entity::Crate, Entity::Present, Entity::Receptacle, Entity::Tree, Entity::Portal(self.place_tokens, self.place_link.clone())];
+            let entities = vec![Entity::Player, Entity::Crate, Entity::Present, Entity::Receptacle, Entity::Tree, Entity::Portal(self.place_tokens, self.place_link.clone()), Entity::Pow, Entity::TreeStump, Entity::ReturnPortal];
 
             let pane_rect = inputs.screen_rect;
 
             let level_pane = pane_rect.fit_aspect_ratio(self.current_level.aspect().max(2.0)).fit_aspect_ratio(self.current_level.aspect());
             rc.push(RenderCommand::solid_rect(level_pane, Vec4::new(1.0, 0.0, 0.0, 1.0), 1.0));
             let level_rect = level_pane.dilate_pc(-0.04);
-            self.current_level.render(level_rect, rc, self.completed_levels.len() as i32, 0.0);
+            self.current_level.render(level_rect, rc, self.profiles.profile().tokens(), 0.0);
 
             for i in 0..self.current_level.w {
                 for j in 0..self.current_level.h {
@@ -127,72 +256,13 @@ impl Session {
             let right_pane = Rect::new(level_pane.right(), 0.0, level_pane.x, inputs.screen_rect.h);
 
             {   
-                if let Some(cmd) = self.terminal.frame(inputs, rc, right_pane) {
-                    match cmd {
-                        TerminalCommand::New(name) => {
-                            if self.level_repository.contains_level(&name) {
-                                self.terminal.tprint(format!("level {} already exists", name));
-                            } else {
-                                self.current_level = Level::new_empty(name);
-                            }
-                        },
-                        TerminalCommand::Load(name) => {
-                            if let Some(existing) = self.level_repository.get_level(&name) {
-                                self.current_level = existing;
-                            } else {
-                                self.terminal.tprint(format!("level {} not found", name));
-                            }
-                        },
-                        TerminalCommand::Save => {
-                            self.level_repository.save_level(self.current_level.title.clone(), self.name.clone(), self.current_level.clone());
-                        },
-                        TerminalCommand::Play => {
-                            self.level_repository.save_level(self.current_level.title.clone(), self.name.clone(), self.current_level.clone());
-                            self.current_instance = Some(Instance::new(self.current_level.instance()));
-                            // maybe check theres a player, an objective, etc
-                        },
-                        TerminalCommand::Reset => {
-                            self.completed_levels = HashSet::new();
-                        },
-                        TerminalCommand::List => {
-                            let mut level_names: Vec<String> = self.level_repository.data.keys().map(|x| x.clone()).collect();
-                            level_names.sort();
-                            for name in level_names {
-                                let mut line = name.clone();
-                                line.insert(0, ' ');
-                                line.insert(0, ' ');
-                                self.terminal.tprint(line);
-                            }
-                        },
-                        TerminalCommand::Link(arg) => {
-                            self.place_link = arg;
-                        },
-                        TerminalCommand::Tokens(arg) => {
-                            self.place_tokens = arg as i32;
-                        },
-                        TerminalCommand::Dims(new_w, new_h) => {
-                            let (old_w, old_h) = (self.current_level.w, self.current_level.h);
-                            self.current_level.w = new_w as i32;
-                            self.current_level.h = new_h as i32;
-                            let old_tiles = self.current_level.tiles.clone();
-                            self.current_level.tiles = vec![Tile::Wall; (new_w * new_h) as usize];
-                            for i in 0..old_w {
-                                for j in 0..old_h {
-                                    if i < self.current_level.w as i32 && j < self.current_level.h as i32 {
-                                        self.current_level.tiles[(j * self.current_level.w + i) as usize] = old_tiles[(j * old_w + i) as usize];
-                                    }
-                                }
-                            }
-                            // ensure entities dont go out of bounds
-                            self.current_level.entities.retain(|(e, x, y)| *x < self.current_level.w && *y < self.current_level.h);
-                        },
-                    }
+                let repo = &self.level_repository;
+                let complete_line = |line: &str| complete(line, &repo.data.keys().cloned().collect::<Vec<String>>());
+                if let Some(line) = self.terminal.frame(inputs, rc, right_pane, &self.profiles.profile().key_repeat, &complete_line) {
+                    run_command(self, &line);
                 }
-                
             }
 
-
-
             {   // Left Pane
                 let left_pane = Rect::new(0.0, 0.0, level_pane.x, pane_rect.h);
                 let left_top = left_pane.child(0.0, 0.0, 1.0, 0.5);
@@ -221,10 +291,14 @@ impl Session {
 
                 let left_bot = left_pane.child(0.0, 0.5, 1.0, 0.5);
                 let entity_pane = left_bot.fit_center_square();
+                let entity_rows = (entities.len() as i32 + 1) / 2;
                 for i in 0..2 {
-                    for j in 0..3 {
+                    for j in 0..entity_rows {
                         let entity_idx = (j * 2 + i) as usize;
-                        let curr_entity_pane = entity_pane.grid_child(i, j, 2, 3);
+                        if entity_idx >= entities.len() {
+                            continue;
+                        }
+                        let curr_entity_pane = entity_pane.grid_child(i, j, 2, entity_rows);
                         let entity_rect = curr_entity_pane.dilate_pc(-0.04);
                         if entity_rect.contains(inputs.mouse_pos) && (inputs.lmb == KeyStatus::Pressed || inputs.lmb == KeyStatus::JustPressed) {
                             self.entity_selection = Some(entity_idx);
@@ -244,4 +318,28 @@ impl Session {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn portal_back_to_the_hub_unwinds() {
+        let mut session = Session::from_parts(Terminal::new(), LevelRepository::in_memory(), ProfileRepository::in_memory(), false);
+        for (name, dest) in [("hub", "lvl"), ("lvl", "hub")] {
+            let level = level(&format!("{}\n:portal 2 1 0 {}\n#####\n#po #\n#####", name, dest));
+            session.level_repository.save_level(name.to_owned(), "santa".to_owned(), level).unwrap();
+        }
+        let hub = session.level_repository.get_level(&"hub".to_owned()).unwrap();
+        session.current_instance = Some(Instance::new(hub.instance(), None));
+        let title = |session: &Session| session.current_instance.as_ref().unwrap().level.l.title.clone();
+
+        session.travel(&session.level_repository.id_of(&"lvl".to_owned()).unwrap(), (2, 1));
+        assert_eq!((title(&session).as_str(), session.return_stack.len()), ("lvl", 1));
+        session.travel(&session.level_repository.id_of(&"hub".to_owned()).unwrap(), (2, 1));
+        assert_eq!((title(&session).as_str(), session.return_stack.len()), ("hub", 0));
+        assert_eq!(session.current_instance.as_ref().unwrap().portal_lock, Some((2, 1)));
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+
+use crate::level::*;
+use crate::rules::*;
+
+// headless breadth first search over rules::step, so designers can check a level actually has a solution
+// state is just the entities, tiles never change
+
+pub const directions: [(i32, i32); 4] = [(0, -1), (0, 1), (-1, 0), (1, 0)];
+pub const solver_max_states: usize = 200_000;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SolveResult {
+    Solved { moves: Vec<(i32, i32)>, nodes: usize },
+    Unsolvable { nodes: usize },
+    Exhausted { nodes: usize }, // no solution within max_states
+}
+
+pub fn solve(level: &Level, max_states: usize) -> SolveResult {
+    let won = |entities: &[(Entity, i32, i32)]| Level::victorious(level.w, level.h, &level.tiles, entities);
+    if won(&level.entities) {
+        return SolveResult::Solved { moves: Vec::new(), nodes: 1 };
+    }
+
+    // child state -> (parent state, move that got us there)
+    let mut parents: HashMap<Entities, (Entities, (i32, i32))> = HashMap::new();
+    let mut seen: HashSet<Entities> = HashSet::new();
+    let mut frontier = VecDeque::new();
+
+    seen.insert(level.entities.clone());
+    frontier.push_back(level.entities.clone());
+
+    while let Some(state) = frontier.pop_front() {
+        for dir in directions {
+            let next = match step(level, &state, dir) {
+                Some((next, _)) => next,
+                None => continue,
+            };
+            if seen.contains(&next) {
+                continue;
+            }
+            if seen.len() >= max_states {
+                return SolveResult::Exhausted { nodes: seen.len() };
+            }
+            seen.insert(next.clone());
+            parents.insert(next.clone(), (state.clone(), dir));
+
+            if won(&next) {
+                // walk back up to the start
+                let mut moves = Vec::new();
+                let mut curr = next;
+                while let Some((parent, dir)) = parents.get(&curr) {
+                    moves.push(*dir);
+                    curr = parent.clone();
+                }
+                moves.reverse();
+                return SolveResult::Solved { moves, nodes: seen.len() };
+            }
+            frontier.push_back(next);
+        }
+    }
+
+    SolveResult::Unsolvable { nodes: seen.len() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn finds_the_shortest_solution() {
+        // going round the top is longer than pushing straight along
+        match solve(&level("short\n#######\n#     #\n#pb  t#\n#######"), solver_max_states) {
+            SolveResult::Solved { moves, .. } => assert_eq!(moves, vec![(1, 0), (1, 0), (1, 0)]),
+            result => panic!("{:?}", result),
+        }
+    }
+
+    #[test]
+    fn cornered_present_is_unsolvable() {
+        assert!(matches!(solve(&level("stuck\n######\n#b pt#\n######"), solver_max_states), SolveResult::Unsolvable { .. }));
+    }
+
+    #[test]
+    fn gives_up_past_max_states() {
+        let open = level("open\n#########\n#p      #\n#       #\n#  b    #\n#      t#\n#########");
+        assert_eq!(solve(&open, 3), SolveResult::Exhausted { nodes: 3 });
+        assert!(matches!(solve(&open, solver_max_states), SolveResult::Solved { .. }));
+    }
+}
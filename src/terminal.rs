@@ -3,33 +3,37 @@ use glutin::event::VirtualKeyCode;
 use crate::lib::kmath::*;
 use crate::lib::kinput::*;
 use crate::renderer::*;
+use crate::commands::quote;
 
 
 pub struct Terminal {
     pub terminal_history: Vec<String>,
     pub terminal_lines: Vec<String>,    // history and prints
     pub terminal_str: String,
+    pub cursor: usize, // byte index into terminal_str, always on a char boundary since completions and history can bring in non ascii
     pub history_idx: Option<i32>,   
+    cycling: Option<(usize, Vec<String>, usize)>, // tab again goes to the next one: where the word starts, candidates, which
+    scroll: f32, // lines up from the bottom, the wheel does fractions
+    history_file: Option<String>, // saved to on every command if its set
 }
 
-// interface point
-pub enum TerminalCommand {
-    New(String),
-    Load(String),
-    Save,
-    // rename, delete etc
-    Play,
-    Dims(u32, u32),
-
-    List, // levels, yeah how am I gonna get feedback
-        // maybe right pane is a terminal and font size is a bit smaller
-        // need a better font too
-        // and we need some aspect ratio / size constraints
-
-    // tbh this works but is kinda ghetto, you could pop up a dialog on place
-    Link(String),
-    Tokens(u32),
-    Reset,
+pub const history_path: &str = "./terminal_history.txt";
+// oldest goes first past these
+pub const max_lines: usize = 1000;
+pub const max_history: usize = 500;
+const num_lines: i32 = 30; // on screen including the input line
+
+// lines of candidates get wrapped at about this many characters
+const candidates_width: usize = 40;
+
+fn common_prefix(words: &[String]) -> String {
+    let mut prefix = words[0].clone();
+    for w in words {
+        while !w.starts_with(&prefix) {
+            prefix.pop();
+        }
+    }
+    prefix
 }
 
 impl Terminal {
@@ -38,101 +42,208 @@ impl Terminal {
             terminal_history: Vec::new(),
             terminal_lines: Vec::new(),
             terminal_str: "".to_owned(),
+            cursor: 0,
             history_idx: None,
+            cycling: None,
+            scroll: 0.0,
+            history_file: None,
         }
     }
 
-    fn term_logic(&mut self, inputs: &FrameInputState, rc: &mut Vec<RenderCommand>) -> Option<TerminalCommand> {
-        let edit = |c, s: &mut String, h: &mut Option<i32>| {
-            s.push(c);
-            *h = None;
-        };
+    // one command per line, missing is fine its just empty
+    pub fn load_history(&mut self, path: &str) {
+        if let Ok(contents) = std::fs::read_to_string(path) {
+            for line in contents.lines() {
+                self.remember(line.to_owned());
+            }
+        }
+        self.history_file = Some(path.to_owned());
+    }
+
+    fn save_history(&self) {
+        if let Some(path) = &self.history_file {
+            let contents: String = self.terminal_history.iter().map(|line| format!("{}\n", line)).collect();
+            if std::fs::write(path, contents).is_err() {
+                println!("couldn't write {}", path);
+            }
+        }
+    }
+
+    fn remember(&mut self, line: String) {
+        self.terminal_history.push(line);
+        if self.terminal_history.len() > max_history {
+            let extra = self.terminal_history.len() - max_history;
+            self.terminal_history.drain(..extra);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.terminal_lines.clear();
+        self.scroll = 0.0;
+    }
+
+    fn scroll_by(&mut self, lines: f32) {
+        let max_scroll = self.terminal_lines.len().saturating_sub(num_lines as usize - 1);
+        self.scroll = (self.scroll + lines).max(0.0).min(max_scroll as f32);
+    }
+
+    // only what the font has
+    pub fn insert(&mut self, text: &str) {
+        for c in text.chars().filter(|c| *c >= ' ' && *c <= '~') {
+            self.terminal_str.insert(self.cursor, c);
+            self.cursor += c.len_utf8();
+            self.history_idx = None;
+        }
+    }
+
+    pub fn delete_back(&mut self) {
+        if self.cursor > 0 {
+            self.cursor = self.prev_char();
+            self.terminal_str.remove(self.cursor);
+        }
+    }
+
+    fn prev_char(&self) -> usize {
+        self.terminal_str[..self.cursor].char_indices().next_back().map_or(0, |(i, _)| i)
+    }
+
+    fn next_char(&self) -> usize {
+        self.terminal_str[self.cursor..].chars().next().map_or(self.cursor, |c| self.cursor + c.len_utf8())
+    }
+
+    // back over any spaces then the word before them, like a shell
+    fn word_start(&self) -> usize {
+        let bytes = self.terminal_str.as_bytes();
+        let mut i = self.cursor;
+        while i > 0 && bytes[i - 1] == b' ' {
+            i -= 1;
+        }
+        while i > 0 && bytes[i - 1] != b' ' {
+            i -= 1;
+        }
+        i
+    }
+
+    fn word_end(&self) -> usize {
+        let bytes = self.terminal_str.as_bytes();
+        let mut i = self.cursor;
+        while i < bytes.len() && bytes[i] == b' ' {
+            i += 1;
+        }
+        while i < bytes.len() && bytes[i] != b' ' {
+            i += 1;
+        }
+        i
+    }
+
+    pub fn delete_word_back(&mut self) {
+        let start = self.word_start();
+        self.terminal_str.replace_range(start..self.cursor, "");
+        self.cursor = start;
+    }
+
+    fn replace_word(&mut self, start: usize, word: &str) {
+        self.terminal_str.replace_range(start..self.cursor, word);
+        self.cursor = start + word.len();
+    }
+
+    // complete gets the line up to the cursor and says where the word starts and what it could be
+    pub fn tab(&mut self, complete: &dyn Fn(&str) -> (usize, Vec<String>)) {
+        if let Some((start, candidates, idx)) = &mut self.cycling {
+            let start = *start;
+            *idx = (*idx + 1) % candidates.len();
+            let word = quote(&candidates[*idx]);
+            self.replace_word(start, &word);
+            return;
+        }
+        let (start, candidates) = complete(&self.terminal_str[..self.cursor]);
+        if candidates.len() == 1 {
+            let mut word = quote(&candidates[0]);
+            if !self.terminal_str[self.cursor..].starts_with(' ') {
+                word.push(' ');
+            }
+            self.replace_word(start, &word);
+        } else if candidates.len() > 1 {
+            let prefix = common_prefix(&candidates);
+            let typed = self.terminal_str[start..self.cursor].trim_start_matches('"').len();
+            if prefix.len() > typed {
+                // as far as they all agree, left open if it needs a quote
+                let was_quoted = self.terminal_str[start..].starts_with('"');
+                let word = if was_quoted || prefix.contains(' ') { format!("\"{}", prefix) } else { prefix };
+                self.replace_word(start, &word);
+            } else {
+                let mut line = String::new();
+                for c in candidates.iter() {
+                    if !line.is_empty() && line.len() + c.len() > candidates_width {
+                        self.tprint(std::mem::take(&mut line));
+                    }
+                    line.push_str(&format!("{}  ", quote(c)));
+                }
+                self.tprint(line);
+                // so the next tab gives the first one
+                let last = candidates.len() - 1;
+                self.cycling = Some((start, candidates, last));
+            }
+        }
+    }
+
+    fn set_line(&mut self, line: String) {
+        self.cursor = line.len();
+        self.terminal_str = line;
+    }
+
+    fn term_logic(&mut self, inputs: &FrameInputState, rc: &mut Vec<RenderCommand>, repeat: &KeyRepeat, complete: &dyn Fn(&str) -> (usize, Vec<String>)) -> Option<String> {
+        let held = |key| inputs.pressed_or_repeated(key, repeat);
+
+        // anything but tab stops cycling through completions
+        if !inputs.text.is_empty() || inputs.keys_pressed_this_frame.iter().any(|k| *k != VirtualKeyCode::Tab) {
+            self.cycling = None;
+        }
+
+        self.insert(&inputs.text);
+
+        // holding these keeps going
+        if held(VirtualKeyCode::Back) {
+            if inputs.ctrl() {
+                self.delete_word_back();
+            } else {
+                self.delete_back();
+            }
+        }
+        if held(VirtualKeyCode::W) && inputs.ctrl() {
+            self.delete_word_back();
+        }
+        if held(VirtualKeyCode::Delete) && self.cursor < self.terminal_str.len() {
+            self.terminal_str.remove(self.cursor);
+        }
+        if held(VirtualKeyCode::Left) {
+            self.cursor = if inputs.ctrl() { self.word_start() } else { self.prev_char() };
+        }
+        if held(VirtualKeyCode::Right) {
+            self.cursor = if inputs.ctrl() { self.word_end() } else { self.next_char() };
+        }
+
         for keystroke in inputs.keys_pressed_this_frame.iter() {
             match keystroke {
-                VirtualKeyCode::Q => edit('q', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::W => edit('w', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::E => edit('e', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::R => edit('r', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::T => edit('t', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::Y => edit('y', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::U => edit('u', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::I => edit('i', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::O => edit('o', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::P => edit('p', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::A => edit('a', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::S => edit('s', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::D => edit('d', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::F => edit('f', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::G => edit('g', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::H => edit('h', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::J => edit('j', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::K => edit('k', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::L => edit('l', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::Z => edit('z', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::X => edit('x', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::C => edit('c', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::V => edit('v', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::B => edit('b', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::N => edit('n', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::M => edit('m', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::Space => edit(' ', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::Key1 => edit('1', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::Key2 => edit('2', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::Key3 => edit('3', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::Key4 => edit('4', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::Key5 => edit('5', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::Key6 => edit('6', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::Key7 => edit('7', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::Key8 => edit('8', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::Key9 => edit('9', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::Key0 => edit('0', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::Minus => edit('-', &mut self.terminal_str, &mut self.history_idx),
-                VirtualKeyCode::Tab => {},// try autocomplete
-                VirtualKeyCode::Return => {
-                    self.terminal_history.push(self.terminal_str.clone());
+                VirtualKeyCode::Home => self.cursor = 0,
+                VirtualKeyCode::End => self.cursor = self.terminal_str.len(),
+                VirtualKeyCode::Tab => self.tab(complete),
+                VirtualKeyCode::PageUp => self.scroll_by((num_lines - 2) as f32),
+                VirtualKeyCode::PageDown => self.scroll_by(-(num_lines - 2) as f32),
+                VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => {
+                    self.remember(self.terminal_str.clone());
+                    self.save_history();
+                    self.scroll = 0.0;
                     let mut display_line = self.terminal_str.clone();
                     display_line.insert(0, '>');
                     self.terminal_lines.push(display_line);
                     let command = self.terminal_str.clone();
-                    self.terminal_str = "".to_owned();
+                    self.set_line("".to_owned());
+                    self.history_idx = None;
 
-                    // feedback is gonna be important esp wrt overwriting
-                    // save vs saveas vs overwrite
-                    // play should autosave
+                    // the line goes back to session which runs it against the command table
                     if !command.is_empty() {
-                        if command.starts_with("open ") && command.split(" ").count() == 2 {
-                            let level_name = command.split(" ").nth(1).unwrap().to_owned();
-                            return Some(TerminalCommand::Load(level_name));
-                        } else if command.starts_with("link ") && command.split(" ").count() == 2 {
-                            let arg = command.split(" ").nth(1).unwrap().to_owned();
-                            return Some(TerminalCommand::Link(arg));
-                        } else if command.starts_with("tokens ") && command.split(" ").count() == 2 {
-                            let arg = command.split(" ").nth(1).unwrap().to_owned();
-                            if let Ok(num) = arg.parse::<u32>() {
-                                return Some(TerminalCommand::Tokens(num));
-                            }
-                        } else if command.starts_with("save") && command.split(" ").count() == 1 {
-                            return Some(TerminalCommand::Save);
-                        } else if command.starts_with("list") && command.split(" ").count() == 1 {
-                            return Some(TerminalCommand::List);
-                        } else if command.starts_with("play") && command.split(" ").count() == 1 {
-                            return Some(TerminalCommand::Play);
-                        } else if command.starts_with("reset") && command.split(" ").count() == 1 {
-                            return Some(TerminalCommand::Reset);
-                        } else if command.starts_with("dims ") && command.split(" ").count() == 3 {
-                            if let Ok(new_w) = command.split(" ").nth(1).unwrap().parse::<u32>() {
-                                if let Ok(new_h) = command.split(" ").nth(2).unwrap().parse::<u32>() {
-                                    return Some(TerminalCommand::Dims(new_w, new_h));
-                                }
-                            }
-                        } else {
-                            self.tprint(format!("bad command: {}", command));
-                        }
-                    }
-                },
-                VirtualKeyCode::Back => {
-                    if !self.terminal_str.is_empty() {
-                        self.terminal_str.pop();
+                        return Some(command);
                     }
                 },
                 // yeah desired up/down arrow key behaviour looks like spaghetti but it is what you want intuitively
@@ -140,14 +251,14 @@ impl Terminal {
                     if let Some(idx) = self.history_idx {
                         // already in history
                         self.history_idx = Some((idx + 1).min((self.terminal_history.len() - 1) as i32));
-                        self.terminal_str = self.terminal_history[self.terminal_history.len() - 1 - self.history_idx.unwrap() as usize].clone();
+                        self.set_line(self.terminal_history[self.terminal_history.len() - 1 - self.history_idx.unwrap() as usize].clone());
                     } else {
                         if self.terminal_history.len() !=  0 {
                             if !self.terminal_str.is_empty() {
-                                self.terminal_history.push(self.terminal_str.clone());
+                                self.remember(self.terminal_str.clone());
                             }
                             self.history_idx = Some(0.min((self.terminal_history.len() - 1) as i32));
-                            self.terminal_str = self.terminal_history[self.terminal_history.len() - 1 - self.history_idx.unwrap() as usize].clone();
+                            self.set_line(self.terminal_history[self.terminal_history.len() - 1 - self.history_idx.unwrap() as usize].clone());
                         }
                     }
                 }
@@ -155,15 +266,15 @@ impl Terminal {
                     if let Some(idx) = self.history_idx {
                         if self.history_idx == Some(0) {
                             self.history_idx = None;
-                            self.terminal_str = "".to_owned();
+                            self.set_line("".to_owned());
                         } else {
                             self.history_idx = Some((idx - 1).max(0));
-                            self.terminal_str = self.terminal_history[self.terminal_history.len() - 1 - self.history_idx.unwrap() as usize].clone();
+                            self.set_line(self.terminal_history[self.terminal_history.len() - 1 - self.history_idx.unwrap() as usize].clone());
                         }
                     } else {
                         if !self.terminal_str.is_empty() {
-                            self.terminal_history.push(self.terminal_str.clone());
-                            self.terminal_str = "".to_owned();
+                            self.remember(self.terminal_str.clone());
+                            self.set_line("".to_owned());
                         }
                     }
                 }
@@ -174,36 +285,162 @@ impl Terminal {
         return None;
     }
 
-    pub fn frame(&mut self, inputs: &FrameInputState, rc: &mut Vec<RenderCommand>, term_rect: Rect) -> Option<TerminalCommand> {
-        let result = self.term_logic(inputs, rc);
-
-        let num_lines = 30;
-        while self.terminal_lines.len() < num_lines as usize {
-            self.terminal_lines.push("".to_owned());
+    pub fn frame(&mut self, inputs: &FrameInputState, rc: &mut Vec<RenderCommand>, term_rect: Rect, repeat: &KeyRepeat, complete: &dyn Fn(&str) -> (usize, Vec<String>)) -> Option<String> {
+        let result = self.term_logic(inputs, rc, repeat, complete);
+        if inputs.scroll != 0.0 && term_rect.contains(inputs.mouse_pos) {
+            self.scroll_by(inputs.scroll);
         }
 
         // rendering goes here
         rc.push(RenderCommand::solid_rect(term_rect, Vec4::new(0.2, 0.2, 0.2, 1.0), 2.0));
 
-        for i in (0..num_lines - 1) {
-            let line_rect = term_rect.grid_child(0, num_lines - i - 1, 1, num_lines);
-            if let Some(line_str) = self.terminal_lines.get(self.terminal_lines.len() - i as usize) {
-                render_text_left(line_str.as_bytes(), line_rect.dilate_pc(-0.01), 2.5, rc);
+        // bottom up from just above the input line
+        let bottom = self.terminal_lines.len() as i32 - 1 - self.scroll as i32;
+        for i in 0..num_lines - 1 {
+            let line_rect = term_rect.grid_child(0, num_lines - i - 2, 1, num_lines);
+            if bottom - i < 0 {
+                break;
             }
+            render_text_left(self.terminal_lines[(bottom - i) as usize].as_bytes(), line_rect.dilate_pc(-0.01), 2.5, rc);
         }
         let line_rect = term_rect.grid_child(0, num_lines - 1, 1, num_lines);
         
         let mut render_str = self.terminal_str.clone();
         render_str.insert(0, '>');
+        render_text_left(render_str.as_bytes(), line_rect.dilate_pc(-0.01), 2.5, rc);
+        // cursor drawn over the top, spaces are blank
         if (inputs.t/ 2.0) % 1.0 > 0.5 {
-            render_str.push('_');
+            let cursor_str = format!("{}_", " ".repeat(self.terminal_str[..self.cursor].chars().count() + 1));
+            render_text_left(cursor_str.as_bytes(), line_rect.dilate_pc(-0.01), 2.6, rc);
         };
-        render_text_left(render_str.as_bytes(), line_rect.dilate_pc(-0.01), 2.5, rc);
         
         return result;
     }
 
     pub fn tprint(&mut self, msg: String) {
-        self.terminal_lines.push(msg)
-    } 
-}
\ No newline at end of file
+        self.terminal_lines.push(msg);
+        if self.terminal_lines.len() > max_lines {
+            self.terminal_lines.remove(0);
+        }
+        // if youre scrolled up reading something it stays put
+        if self.scroll > 0.0 {
+            self.scroll_by(1.0);
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    fn typed(s: &str) -> Terminal {
+        let mut terminal = Terminal::new();
+        terminal.insert(s);
+        terminal
+    }
+
+    #[test]
+    fn edits_at_the_cursor() {
+        let mut terminal = typed("open level");
+        terminal.cursor = 5;
+        terminal.insert("\"First_");
+        assert_eq!(terminal.terminal_str, "open \"First_level");
+        terminal.delete_back();
+        assert_eq!(terminal.terminal_str, "open \"Firstlevel");
+        assert_eq!(terminal.cursor, 11);
+        // nothing the font cant draw
+        terminal.insert("é\t");
+        assert_eq!(terminal.terminal_str, "open \"Firstlevel");
+    }
+
+    #[test]
+    fn cursor_steps_over_non_ascii() {
+        let mut terminal = typed("open ca");
+        terminal.tab(&|line| crate::commands::complete(line, &["café".to_owned()]));
+        assert_eq!(terminal.terminal_str, "open café ");
+        terminal.cursor = terminal.prev_char();
+        terminal.cursor = terminal.prev_char();
+        terminal.insert("x");
+        assert_eq!(terminal.terminal_str, "open cafxé ");
+        terminal.cursor = terminal.next_char();
+        terminal.delete_back();
+        assert_eq!(terminal.terminal_str, "open cafx ");
+    }
+
+    fn complete_levels(line: &str) -> (usize, Vec<String>) {
+        crate::commands::complete(line, &["first level".to_owned(), "fifth".to_owned(), "ice1".to_owned(), "ice2".to_owned()])
+    }
+
+    #[test]
+    fn tab_completes_and_cycles() {
+        let mut terminal = typed("op");
+        terminal.tab(&complete_levels);
+        assert_eq!(terminal.terminal_str, "open ");
+        terminal.tab(&complete_levels);
+        assert_eq!(terminal.terminal_lines.last().unwrap(), "fifth  \"first level\"  ice1  ice2  ");
+        terminal.insert("fi");
+        terminal.cycling = None;
+        terminal.tab(&complete_levels);
+        assert_eq!(terminal.terminal_str, "open fi");
+        assert_eq!(terminal.terminal_lines.last().unwrap(), "fifth  \"first level\"  ");
+        terminal.tab(&complete_levels);
+        assert_eq!(terminal.terminal_str, "open fifth");
+        terminal.tab(&complete_levels);
+        assert_eq!(terminal.terminal_str, "open \"first level\"");
+        terminal.tab(&complete_levels);
+        assert_eq!(terminal.terminal_str, "open fifth");
+    }
+
+    #[test]
+    fn tab_fills_common_prefix() {
+        let mut terminal = typed("open \"fir");
+        terminal.tab(&complete_levels);
+        assert_eq!(terminal.terminal_str, "open \"first level\" ");
+        let mut terminal = typed("link i");
+        terminal.tab(&complete_levels);
+        assert_eq!(terminal.terminal_str, "link ice");
+    }
+
+    #[test]
+    fn bounded_scrollback() {
+        let mut terminal = Terminal::new();
+        for i in 0..max_lines + 10 {
+            terminal.tprint(i.to_string());
+        }
+        assert_eq!(terminal.terminal_lines.len(), max_lines);
+        assert_eq!(terminal.terminal_lines[0], "10");
+        terminal.scroll_by(1e6);
+        assert_eq!(terminal.scroll as usize, max_lines - (num_lines as usize - 1));
+        terminal.clear();
+        terminal.scroll_by(5.0);
+        assert_eq!(terminal.scroll, 0.0);
+    }
+
+    #[test]
+    fn history_survives_restart() {
+        let path = &temp_path("history.txt");
+        let mut terminal = Terminal::new();
+        terminal.load_history(path);
+        for i in 0..max_history + 3 {
+            terminal.remember(format!("open {}", i));
+        }
+        terminal.save_history();
+        let mut terminal = Terminal::new();
+        terminal.load_history(path);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(terminal.terminal_history.len(), max_history);
+        assert_eq!(terminal.terminal_history.last().unwrap(), &format!("open {}", max_history + 2));
+    }
+
+    #[test]
+    fn deletes_words() {
+        let mut terminal = typed("copy first  second");
+        terminal.delete_word_back();
+        assert_eq!(terminal.terminal_str, "copy first  ");
+        terminal.delete_word_back();
+        assert_eq!(terminal.terminal_str, "copy ");
+        terminal.cursor = 2;
+        terminal.delete_word_back();
+        assert_eq!((terminal.terminal_str.as_str(), terminal.cursor), ("py ", 0));
+    }
+}
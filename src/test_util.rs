@@ -0,0 +1,11 @@
+// bits the test modules share
+use crate::level::*;
+
+pub fn level(s: &str) -> Level {
+    Level::from_string(s).expect("fixture doesnt parse")
+}
+
+// in the temp dir and only this test run's, so runs dont trip over each other
+pub fn temp_path(name: &str) -> String {
+    std::env::temp_dir().join(format!("skrs_{}_{}", std::process::id(), name)).to_str().unwrap().to_owned()
+}
@@ -0,0 +1,144 @@
+use crate::level::*;
+use crate::rules::*;
+use crate::lib::kmath::*;
+
+// the move has already happened by the time we animate it, so this just remembers the cells
+// each thing went through and when, and render asks where it should be drawn at time t
+// one tick of the rules engine is one slot in the timeline: the push is a bit slower than slides
+
+pub const step_time: f32 = 0.1;
+pub const slide_time: f32 = 0.06;
+
+type Segment = ((i32, i32), (i32, i32), f32, f32); // from, to, start, end
+
+// one thing moving: the cells it went through
+struct Track {
+    entity: Entity,
+    end: (i32, i32),
+    segments: Vec<Segment>,
+}
+
+pub struct Tweens {
+    tracks: Vec<Track>,
+    done_at: f32,
+    slides: bool, // anything went past tick 0
+}
+
+impl Tweens {
+    pub fn new() -> Tweens {
+        Tweens {
+            tracks: Vec::new(),
+            done_at: 0.0,
+            slides: false,
+        }
+    }
+
+    pub fn busy(&self, t: f32) -> bool {
+        t < self.done_at
+    }
+
+    pub fn sliding(&self, t: f32) -> bool {
+        self.busy(t) && self.slides
+    }
+
+    // whatever was playing is dropped, things snap to where they are and start from there
+    pub fn start(&mut self, events: &[Event], t: f32) {
+        self.tracks.clear();
+        self.done_at = t;
+        self.slides = false;
+        if events.contains(&Event::Restart) {
+            return;
+        }
+
+        // undo comes through as the moves backwards, so the last tick goes first
+        let backwards = events.contains(&Event::Undo);
+        let moves: Vec<Movement> = events.iter().filter_map(|ev| ev.movement()).collect();
+        let max_tick = match moves.iter().map(|m| m.3).max() {
+            Some(max_tick) => max_tick,
+            None => return,
+        };
+        let slot_time = |tick: usize| if tick == 0 { step_time } else { slide_time };
+        let mut slot_start = vec![0.0; max_tick + 1];
+        let mut acc = t;
+        let order: Vec<usize> = if backwards { (0..=max_tick).rev().collect() } else { (0..=max_tick).collect() };
+        for tick in order {
+            slot_start[tick] = acc;
+            acc += slot_time(tick);
+        }
+        self.done_at = acc;
+        self.slides = max_tick > 0;
+
+        for (entity, from, to, tick) in moves {
+            let segment = (from, to, slot_start[tick], slot_start[tick] + slot_time(tick));
+            // a chain moves things into cells that are being left in the same tick, those are different tracks
+            match self.tracks.iter_mut().find(|tr| tr.entity == *entity && tr.end == from && tr.segments.last().unwrap().3 <= segment.2) {
+                Some(track) => {
+                    track.end = to;
+                    track.segments.push(segment);
+                },
+                None => self.tracks.push(Track { entity: entity.clone(), end: to, segments: vec![segment] }),
+            }
+        }
+    }
+
+    // how far from (i, j) to draw whatever is there, in cells
+    pub fn offset(&self, e: &Entity, i: i32, j: i32, t: f32) -> Vec2 {
+        let track = match self.tracks.iter().find(|tr| tr.entity == *e && tr.end == (i, j)) {
+            Some(track) => track,
+            None => return Vec2::new(0.0, 0.0),
+        };
+        let mut at = Vec2::new(track.segments[0].0.0 as f32, track.segments[0].0.1 as f32);
+        for (from, to, start, end) in track.segments.iter() {
+            if t >= *end {
+                at = Vec2::new(to.0 as f32, to.1 as f32);
+            } else if t > *start {
+                let from = Vec2::new(from.0 as f32, from.1 as f32);
+                let to = Vec2::new(to.0 as f32, to.1 as f32);
+                at = from.lerp(to, (t - start) / (end - start));
+                break;
+            } else {
+                break;
+            }
+        }
+        Vec2::new(at.x - i as f32, at.y - j as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    fn slide_level() -> LevelInstance {
+        level("tween\n#######\n#pB// #\n#######").instance()
+    }
+
+    #[test]
+    fn slides_travel_cell_by_cell() {
+        let mut li = slide_level();
+        li.try_move((1, 0));
+        let mut tweens = Tweens::new();
+        tweens.start(&li.take_events(), 0.0);
+        // present ends up at 5, pushed to 3 then slides 4, 5
+        assert_eq!(tweens.offset(&Entity::Present, 5, 1, 0.0), Vec2::new(-3.0, 0.0));
+        assert_eq!(tweens.offset(&Entity::Present, 5, 1, step_time), Vec2::new(-2.0, 0.0));
+        assert_eq!(tweens.offset(&Entity::Present, 5, 1, step_time + slide_time), Vec2::new(-1.0, 0.0));
+        assert!(tweens.busy(step_time + slide_time));
+        assert!(!tweens.busy(step_time + 3.0 * slide_time));
+        assert_eq!(tweens.offset(&Entity::Present, 5, 1, 10.0), Vec2::new(0.0, 0.0));
+    }
+
+    #[test]
+    fn undo_plays_backwards() {
+        let mut li = slide_level();
+        li.try_move((1, 0));
+        li.undo();
+        let mut tweens = Tweens::new();
+        let events = li.take_events();
+        tweens.start(&events[events.iter().position(|ev| *ev == Event::Undo).unwrap()..], 0.0);
+        // the slide comes back first, the push last
+        assert_eq!(tweens.offset(&Entity::Present, 2, 1, 0.0), Vec2::new(3.0, 0.0));
+        assert_eq!(tweens.offset(&Entity::Present, 2, 1, 2.0 * slide_time), Vec2::new(1.0, 0.0));
+        assert_eq!(tweens.offset(&Entity::Present, 2, 1, 2.0 * slide_time + step_time), Vec2::new(0.0, 0.0));
+    }
+}
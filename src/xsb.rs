@@ -0,0 +1,266 @@
+use std::collections::HashSet;
+
+use crate::level::*;
+
+// standard sokoban notation, so we can pull in the classic collections and hand ours to other tools
+// #  wall        @  player      +  player on goal
+// $  box         .  goal        *  box on goal
+// space, - or _  floor
+// a sok file is a bunch of these with titles and comments in between
+
+fn is_map_line(line: &str) -> bool {
+    line.contains('#') && line.chars().all(|c| "#@+$.* -_".contains(c))
+}
+
+pub fn level_from_xsb(title: String, s: &str) -> Result<Level, String> {
+    let lines: Vec<&str> = s.lines().map(|l| l.trim_end()).filter(|l| !l.is_empty()).collect();
+    let h = lines.len();
+    let w = lines.iter().map(|l| l.len()).max().unwrap_or(0);
+    if h == 0 || !lines.iter().all(|l| is_map_line(l)) {
+        return Err(format!("{} isnt a map", title));
+    }
+
+    let mut level = Level {
+        title,
+        w: w as i32,
+        h: h as i32,
+        tiles: vec![Tile::Wall; w*h],
+        entities: vec![],
+    };
+
+    let mut player = None;
+    for (j, line) in lines.iter().enumerate() {
+        for (i, c) in line.chars().enumerate() {
+            let (i, j) = (i as i32, j as i32);
+            if c != '#' {
+                level.tiles[j as usize * w + i as usize] = Tile::Snow;
+            }
+            match c {
+                '@' => level.entities.push((Entity::Player, i, j)),
+                '+' => {
+                    level.entities.push((Entity::Player, i, j));
+                    level.entities.push((Entity::Receptacle, i, j));
+                },
+                '$' => level.entities.push((Entity::Present, i, j)),
+                '.' => level.entities.push((Entity::Receptacle, i, j)),
+                '*' => {
+                    level.entities.push((Entity::Present, i, j));
+                    level.entities.push((Entity::Receptacle, i, j));
+                },
+                _ => {},
+            }
+            if c == '@' || c == '+' {
+                player = Some((i, j));
+            }
+        }
+    }
+
+    // floor the player cant get to is outside the level, wall it up so nothing can walk off the edge
+    let mut inside = HashSet::new();
+    let mut todo = vec![player.ok_or(format!("{} has no player", level.title))?];
+    while let Some((i, j)) = todo.pop() {
+        if i < 0 || j < 0 || i >= level.w || j >= level.h {
+            return Err(format!("{} isnt closed off", level.title));
+        }
+        if level.tiles[(j * level.w + i) as usize] == Tile::Wall || !inside.insert((i, j)) {
+            continue;
+        }
+        todo.extend([(i+1, j), (i-1, j), (i, j+1), (i, j-1)]);
+    }
+    for i in 0..level.w {
+        for j in 0..level.h {
+            if !inside.contains(&(i, j)) {
+                level.tiles[(j * level.w + i) as usize] = Tile::Wall;
+            }
+        }
+    }
+    level.entities.retain(|(_, i, j)| inside.contains(&(*i, *j)));
+
+    Ok(level)
+}
+
+// sok collection, levels are the maps and the lines in between are titles, authors, comments etc
+// if theres a Title: before the first map then titles go before maps, otherwise after (the sok standard)
+// no Title: at all falls back to the last ; comment before the map
+// one result per map so the ones that didnt parse can be reported
+pub fn levels_from_sok(s: &str) -> Vec<Result<Level, String>> {
+    // (lines before, map)
+    let mut chunks: Vec<(Vec<&str>, Vec<&str>)> = Vec::new();
+    let mut between = Vec::new();
+    let mut map = Vec::new();
+    for line in s.lines().map(|l| l.trim_end()) {
+        if is_map_line(line) {
+            map.push(line);
+        } else {
+            if !map.is_empty() {
+                chunks.push((between, map));
+                between = Vec::new();
+                map = Vec::new();
+            }
+            between.push(line);
+        }
+    }
+    if !map.is_empty() {
+        chunks.push((std::mem::take(&mut between), map));
+    }
+
+    let title_in = |lines: &[&str]| lines.iter().find_map(|l| l.strip_prefix("Title:")).map(|t| t.trim().to_owned());
+    let comment_in = |lines: &[&str]| lines.iter().rev().find_map(|l| l.strip_prefix(';')).map(|t| t.trim().to_owned()).filter(|t| !t.is_empty());
+    let titles_first = chunks.first().map(|(before, _)| title_in(before).is_some()).unwrap_or(false);
+
+    (0..chunks.len()).map(|idx| {
+        let before = &chunks[idx].0;
+        let after = chunks.get(idx + 1).map(|(b, _)| b.as_slice()).unwrap_or(&between);
+        let title = if titles_first { title_in(before) } else { title_in(after) }
+            .or(comment_in(before))
+            .unwrap_or(format!("xsb {}", idx + 1));
+        level_from_xsb(title, &chunks[idx].1.join("\n"))
+    }).collect()
+}
+
+// returns the map and a list of whatever didnt make it
+pub fn level_to_xsb(level: &Level) -> (String, Vec<String>) {
+    let mut lossy = Vec::new();
+    let mut lines = Vec::new();
+    // xsb only has the one player, the first one in the level gets to be it
+    let first_player = level.entities.iter().find(|(e, _, _)| *e == Entity::Player).map(|(_, i, j)| (*i, *j));
+    // off the map is wall for us but xsb wants it closed off, so floor at the edge gets a border round it
+    let tile_at = |i: i32, j: i32| if i < 0 || j < 0 || i >= level.w || j >= level.h { Tile::Wall } else { level.tiles[(j * level.w + i) as usize] };
+    let open_edge = (0..level.w).any(|i| tile_at(i, 0) != Tile::Wall || tile_at(i, level.h - 1) != Tile::Wall)
+        || (0..level.h).any(|j| tile_at(0, j) != Tile::Wall || tile_at(level.w - 1, j) != Tile::Wall);
+    let border = if open_edge { 1 } else { 0 };
+    for j in -border..level.h + border {
+        let mut line = String::new();
+        for i in -border..level.w + border {
+            let here: Vec<&Entity> = level.entities.iter().filter(|(_, ei, ej)| *ei == i && *ej == j).map(|(e, _, _)| e).collect();
+            let tile = tile_at(i, j);
+            if tile == Tile::Ice {
+                lossy.push(format!("ice at {},{} exported as floor", i, j));
+            }
+            let goal = here.contains(&&Entity::Receptacle);
+            let player = here.contains(&&Entity::Player) && first_player == Some((i, j));
+            let present = here.contains(&&Entity::Present);
+            for e in here.iter() {
+                match e {
+                    Entity::Player if !player => lossy.push(format!("extra player at {},{} dropped", i, j)),
+                    Entity::Player | Entity::Present | Entity::Receptacle => {},
+                    Entity::Crate => lossy.push(format!("crate at {},{} exported as box", i, j)),
+                    e => lossy.push(format!("{:?} at {},{} dropped", e, i, j)),
+                }
+            }
+            let crate_here = here.contains(&&Entity::Crate);
+            line.push(match (tile, player, present || crate_here, goal) {
+                (Tile::Wall, _, _, _) => '#',
+                (_, true, _, true) => '+',
+                (_, true, _, false) => '@',
+                (_, _, true, true) => '*',
+                (_, _, true, false) => '$',
+                (_, _, _, true) => '.',
+                _ => ' ',
+            });
+        }
+        lines.push(line.trim_end().to_owned());
+    }
+    (lines.join("\n"), lossy)
+}
+
+pub fn levels_to_sok(levels: &[Level]) -> (String, Vec<String>) {
+    let mut out = String::new();
+    let mut lossy = Vec::new();
+    for level in levels {
+        let (map, level_lossy) = level_to_xsb(level);
+        out.push_str(&format!("Title: {}\n{}\n\n", level.title, map));
+        lossy.extend(level_lossy.into_iter().map(|l| format!("{}: {}", level.title, l)));
+    }
+    (out, lossy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    // a couple of the usual suspects, titles after the map, and two that cant be played
+    const collection: &str = "\
+; classic collection
+
+    #####
+    #   #
+    #$  #
+  ###  $##
+  #  $ $ #
+### # ## #   ######
+#   # ## #####  ..#
+# $  $          ..#
+##### ### #@##  ..#
+    #     #########
+    #######
+Title: first
+
+  ####
+###  ####
+#     $ #
+# #  #$ #
+# . .#@ #
+#########
+Title: second
+
+#####
+#   #
+# $.#
+#####
+Title: nobody home
+
+#####
+ @$.#
+#####
+Title: leaky
+";
+
+    #[test]
+    fn reads_a_collection() {
+        let results = levels_from_sok(collection);
+        let titles: Vec<String> = results.iter().filter_map(|r| r.as_ref().ok()).map(|l| l.title.clone()).collect();
+        assert_eq!(titles, vec!["first".to_owned(), "second".to_owned()]);
+        let errors: Vec<String> = results.into_iter().filter_map(|r| r.err()).collect();
+        assert_eq!(errors, vec!["nobody home has no player".to_owned(), "leaky isnt closed off".to_owned()]);
+
+        let first = level_from_xsb("first".to_owned(), collection.split("Title").next().unwrap().trim_start_matches("; classic collection")).unwrap();
+        let count = |e: Entity| first.entities.iter().filter(|(x, _, _)| *x == e).count();
+        assert_eq!((count(Entity::Player), count(Entity::Present), count(Entity::Receptacle)), (1, 6, 6));
+        // the space outside the walls isnt floor
+        assert_eq!(first.tiles[0], Tile::Wall);
+    }
+
+    #[test]
+    fn export_round_trips() {
+        let levels: Vec<Level> = levels_from_sok(collection).into_iter().filter_map(|r| r.ok()).collect();
+        let (sok, lossy) = levels_to_sok(&levels);
+        assert!(lossy.is_empty());
+        let again: Vec<Level> = levels_from_sok(&sok).into_iter().map(|r| r.unwrap()).collect();
+        assert_eq!(again, levels);
+    }
+
+    #[test]
+    fn open_edges_get_walled_in() {
+        let level = level("edge\n#####\npbt  \n#####");
+        let (map, lossy) = level_to_xsb(&level);
+        assert_eq!(map, "#######\n#######\n#@$.  #\n#######\n#######");
+        assert!(lossy.is_empty());
+        let again = level_from_xsb("edge".to_owned(), &map).unwrap();
+        assert_eq!(again.entities.len(), 3);
+    }
+
+    #[test]
+    fn export_reports_what_it_loses() {
+        let level = level("lossy\n#######\n#p/cxp#\n#######");
+        let (map, lossy) = level_to_xsb(&level);
+        assert_eq!(map, "#######\n#@ $  #\n#######");
+        assert_eq!(lossy, vec![
+            "ice at 2,1 exported as floor".to_owned(),
+            "crate at 3,1 exported as box".to_owned(),
+            "Pow at 4,1 dropped".to_owned(),
+            "extra player at 5,1 dropped".to_owned(),
+        ]);
+    }
+}